@@ -0,0 +1,184 @@
+// Copyright (C) 2024-2025 Marcus L. Hanestad <marlhan@proton.me>
+
+//! Measures how fast `scapsrc` turns captured frames into buffers.
+//!
+//! Captures `FRAMES` frames at `FPS` into a `fakesink` and reports the rate
+//! buffers were delivered at and how long after capture they got to the sink.
+//! The delay from capture to the sink includes waiting for `create()` to be
+//! called, so it grows once `create()` can't keep up with scap.
+//!
+//! With `SCAPSRC_BENCH_SAVE_BASELINE` set the numbers are saved as the
+//! baseline, which later runs are compared against. Run with a 4K display
+//! refreshing at 120 Hz on the old revision and then on the new one:
+//!
+//! ```text
+//! SCAPSRC_BENCH_SAVE_BASELINE=1 cargo bench --features capture-tests --bench create
+//! cargo bench --features capture-tests --bench create
+//! ```
+
+use gst::prelude::*;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+const FPS: u32 = 120;
+const FRAMES: i32 = 1200;
+/// Frames skipped before measuring, while capturing ramps up
+const WARMUP_FRAMES: usize = 120;
+/// Where the numbers of the run to compare against are kept
+const BASELINE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/create-baseline");
+
+/// Numbers of one run, with the delays in milliseconds
+#[derive(Clone, Copy)]
+struct Results {
+    fps: f64,
+    median_delay: f64,
+    p99_delay: f64,
+    max_delay: f64,
+}
+
+impl Results {
+    fn parse(s: &str) -> Option<Self> {
+        let mut values = s.split_whitespace().map(|value| value.parse().ok());
+        Some(Self {
+            fps: values.next()??,
+            median_delay: values.next()??,
+            p99_delay: values.next()??,
+            max_delay: values.next()??,
+        })
+    }
+
+    fn to_line(self) -> String {
+        format!(
+            "{} {} {} {}\n",
+            self.fps, self.median_delay, self.p99_delay, self.max_delay
+        )
+    }
+}
+
+/// `new` next to `baseline` with the change in percent
+fn compare(name: &str, baseline: f64, new: f64, unit: &str) {
+    let change = (new - baseline) / baseline * 100.0;
+    println!("{name}: {baseline:.2} {unit} -> {new:.2} {unit} ({change:+.1}%)");
+}
+
+/// Current time on the clock `scapsrc` stamps the capture time meta with
+fn capture_clock_now() -> u64 {
+    if cfg!(target_os = "windows") {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+    } else {
+        gst::glib::monotonic_time() as u64 * 1000
+    }
+}
+
+fn main() {
+    gst::init().unwrap();
+    scapgst::register().unwrap();
+
+    let pipeline = gst::parse::launch(&format!(
+        "scapsrc fps={FPS} num-buffers={FRAMES} ! fakesink name=sink sync=false signal-handoffs=true"
+    ))
+    .unwrap()
+    .downcast::<gst::Pipeline>()
+    .unwrap();
+
+    // Arrival instant and capture to sink delay of every buffer
+    let samples = Arc::new(Mutex::new(Vec::with_capacity(FRAMES as usize)));
+    let capture_time_caps = gst::Caps::new_empty_simple("timestamp/x-scap-capture-time");
+    let sink = pipeline.by_name("sink").unwrap();
+    sink.connect("handoff", false, {
+        let samples = samples.clone();
+        move |args| {
+            let buffer = args[1].get::<gst::Buffer>().unwrap();
+            let delay = buffer
+                .iter_meta::<gst::ReferenceTimestampMeta>()
+                .find(|meta| meta.reference().can_intersect(&capture_time_caps))
+                .map(|meta| capture_clock_now().saturating_sub(meta.timestamp().nseconds()));
+            samples.lock().unwrap().push((Instant::now(), delay));
+            None
+        }
+    });
+
+    pipeline.set_state(gst::State::Playing).unwrap();
+
+    let bus = pipeline.bus().unwrap();
+    for msg in bus.iter_timed(gst::ClockTime::NONE) {
+        match msg.view() {
+            gst::MessageView::Eos(_) => break,
+            gst::MessageView::Error(err) => {
+                pipeline.set_state(gst::State::Null).unwrap();
+                panic!("{} ({:?})", err.error(), err.debug());
+            }
+            _ => {}
+        }
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+
+    let samples = samples.lock().unwrap();
+    let measured = samples.get(WARMUP_FRAMES..).unwrap_or_default();
+    let (Some((first, _)), Some((last, _))) = (measured.first(), measured.last()) else {
+        panic!("Only {} frames were delivered", samples.len());
+    };
+
+    let elapsed = last.duration_since(*first).as_secs_f64();
+    let fps = (measured.len() - 1) as f64 / elapsed;
+
+    let mut delays = measured
+        .iter()
+        .filter_map(|(_, delay)| *delay)
+        .collect::<Vec<_>>();
+    delays.sort_unstable();
+    let delay_ms = |quantile: f64| {
+        let index = ((delays.len() - 1) as f64 * quantile) as usize;
+        delays[index] as f64 / 1_000_000.0
+    };
+
+    println!("Delivered {} frames at {fps:.1} fps", measured.len());
+    if delays.is_empty() {
+        panic!("No buffer had the capture time meta");
+    }
+
+    let results = Results {
+        fps,
+        median_delay: delay_ms(0.5),
+        p99_delay: delay_ms(0.99),
+        max_delay: delay_ms(1.0),
+    };
+    println!(
+        "Capture to sink delay: median {:.2} ms, 99th percentile {:.2} ms, max {:.2} ms",
+        results.median_delay, results.p99_delay, results.max_delay,
+    );
+
+    if std::env::var_os("SCAPSRC_BENCH_SAVE_BASELINE").is_some() {
+        fs::write(BASELINE_PATH, results.to_line()).unwrap();
+        println!("Saved as the baseline");
+        return;
+    }
+
+    let Some(baseline) = fs::read_to_string(BASELINE_PATH)
+        .ok()
+        .and_then(|s| Results::parse(&s))
+    else {
+        println!("No baseline to compare against, save one with SCAPSRC_BENCH_SAVE_BASELINE=1");
+        return;
+    };
+
+    println!("Compared to the baseline:");
+    compare("Frame rate", baseline.fps, results.fps, "fps");
+    compare(
+        "Median delay",
+        baseline.median_delay,
+        results.median_delay,
+        "ms",
+    );
+    compare(
+        "99th percentile delay",
+        baseline.p99_delay,
+        results.p99_delay,
+        "ms",
+    );
+    compare("Max delay", baseline.max_delay, results.max_delay, "ms");
+}
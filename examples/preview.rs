@@ -3,7 +3,7 @@ use gst::MessageView;
 
 fn main() {
     gst::init().unwrap();
-    scapgst::plugin_register_static().unwrap();
+    scapgst::register().unwrap();
 
     let pipeline = gst::parse::launch("scapsrc ! videoconvert ! autovideosink").unwrap();
     let bus = pipeline.bus().unwrap();
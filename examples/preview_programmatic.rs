@@ -6,7 +6,7 @@ use gst::MessageView;
 
 fn main() {
     gst::init().unwrap();
-    scapgst::plugin_register_static().unwrap();
+    scapgst::register().unwrap();
 
     let pipeline = gst::Pipeline::default();
 
@@ -3,7 +3,7 @@ use gst::MessageView;
 
 fn main() {
     gst::init().unwrap();
-    scapgst::plugin_register_static().unwrap();
+    scapgst::register().unwrap();
 
     let pipeline = gst::parse::launch(
         "scapsrc ! videoconvert ! x264enc ! matroskamux ! filesink location=screencast.mkv",
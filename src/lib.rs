@@ -4,6 +4,18 @@ use gst::glib;
 
 mod scapsrc;
 
+pub use scapsrc::{
+    parse_error_message, FrameCapture, ScapError, ScapErrorCode, ScapSrc, CAPTURE_CONTEXT_TYPE,
+};
+
+/// Register the plugin with the default registry of an application that
+/// embeds it, instead of loading it from `GST_PLUGIN_PATH`.
+///
+/// Call this once after `gst::init()`.
+pub fn register() -> Result<(), glib::BoolError> {
+    plugin_register_static()
+}
+
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     scapsrc::register(plugin)?;
     Ok(())
@@ -1,7 +1,13 @@
 // Copyright (C) 2024-2025 Marcus L. Hanestad <marlhan@proton.me>
 
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc;
 use std::sync::LazyLock;
-use std::sync::Mutex;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use gst::glib;
 use gst::prelude::*;
@@ -11,9 +17,58 @@ use gst_base::subclass::base_src::CreateSuccess;
 use gst_base::subclass::prelude::*;
 use scap::capturer::Capturer;
 
+use super::{
+    ChannelOrder, OutputFormat, ScalingMode, ScapError, ScapErrorCode, TargetKind,
+    UnsupportedFormatAction, CAPTURE_CONTEXT_TYPE,
+};
+
 const DEFAULT_FPS: u32 = 25;
 const DEFAULT_SHOW_CURSOR: bool = true;
 const DEFAULT_PERFORM_INTERNAL_PREROLL: bool = false;
+const DEFAULT_DURATION: Option<gst::ClockTime> = gst::ClockTime::NONE;
+const DEFAULT_OUTPUT_FORMAT: OutputFormat = OutputFormat::Bgr0;
+const DEFAULT_STARTUP_DELAY_MS: u32 = 0;
+const DEFAULT_EMIT_SIGNALS: bool = false;
+const DEFAULT_MAX_FPS: u32 = 0;
+const DEFAULT_HEARTBEAT_INTERVAL: u32 = 0;
+const DEFAULT_AUTO_CONVERT: bool = false;
+const DEFAULT_MAX_RETRIES: u32 = 0;
+const DEFAULT_OUTPUT_WIDTH: u32 = 0;
+const DEFAULT_OUTPUT_HEIGHT: u32 = 0;
+const DEFAULT_PAUSED: bool = false;
+const DEFAULT_CROP_X: u32 = 0;
+const DEFAULT_CROP_Y: u32 = 0;
+const DEFAULT_CROP_WIDTH: u32 = 0;
+const DEFAULT_CROP_HEIGHT: u32 = 0;
+const DEFAULT_SCALING_MODE: ScalingMode = ScalingMode::Stretch;
+const DEFAULT_FREEZE_ON_PAUSE: bool = false;
+const DEFAULT_ADAPTIVE_FPS: bool = false;
+const DEFAULT_MIN_FPS: u32 = 1;
+const DEFAULT_ON_UNSUPPORTED_FORMAT: UnsupportedFormatAction = UnsupportedFormatAction::Error;
+const DEFAULT_TARGET: &str = "default";
+const DEFAULT_FIXED_OUTPUT_SIZE: bool = false;
+const DEFAULT_START_RETRIES: u32 = 0;
+const DEFAULT_START_RETRY_DELAY_MS: u32 = 500;
+const DEFAULT_MAX_WIDTH: u32 = 0;
+const DEFAULT_MAX_HEIGHT: u32 = 0;
+const DEFAULT_SMOOTH_TIMESTAMPS: bool = false;
+const DEFAULT_TS_OFFSET: i64 = 0;
+const DEFAULT_DETERMINISTIC_TIMING: bool = false;
+const DEFAULT_DETECT_BLACK_FRAMES: bool = false;
+const DEFAULT_BLACK_FRAME_THRESHOLD: u32 = 50;
+const DEFAULT_EVEN_DIMENSIONS: bool = false;
+const DEFAULT_FALLBACK_ON_STALL: bool = false;
+const DEFAULT_STALL_TIMEOUT_MS: u32 = 1000;
+/// Frames kept for `create()` before dropping the oldest ones
+const MAX_QUEUED_FRAMES: usize = 2;
+/// How long stopping waits for a capturer blocked on getting a frame
+const CAPTURE_STOP_TIMEOUT: Duration = Duration::from_millis(500);
+/// Least time between two latency messages posted as the capture latency moved
+const LATENCY_MESSAGE_INTERVAL: Duration = Duration::from_secs(1);
+/// Width of the thumbnails from `get-target-thumbnail`
+const THUMBNAIL_WIDTH: u32 = 320;
+/// Pixels checked per row and column by `detect-black-frames`
+const BLACK_FRAME_SAMPLES: u32 = 8;
 
 static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
     gst::DebugCategory::new(
@@ -23,11 +78,21 @@ static CAT: LazyLock<gst::DebugCategory> = LazyLock::new(|| {
     )
 });
 
+/// Reference of the `ReferenceTimestampMeta` carrying scap's capture time
+static CAPTURE_TIME_CAPS: LazyLock<gst::Caps> =
+    LazyLock::new(|| gst::Caps::new_empty_simple("timestamp/x-scap-capture-time"));
+
+/// Capturers built by the process, so an abandoned `CaptureThread` can tell
+/// whether another one was built after its own
+static CAPTURERS_BUILT: AtomicU64 = AtomicU64::new(0);
+
 struct FrameInfo {
     width: u32,
     height: u32,
     gst_v_format: gst_video::VideoFormat,
     pts: u64,
+    /// Not in a format that can be output, so it must be converted
+    needs_conversion: bool,
 }
 
 macro_rules! frame_info {
@@ -37,6 +102,7 @@ macro_rules! frame_info {
             height: $frame.height as u32,
             gst_v_format: $gst_fmt,
             pts: $frame.display_time,
+            needs_conversion: false,
         }
     };
 }
@@ -53,12 +119,405 @@ impl FrameInfo {
             _ => return None,
         })
     }
+
+    /// Info of frames that can only be output after converting them
+    pub fn new_convertible(frame: &scap::frame::Frame) -> Option<Self> {
+        match frame {
+            scap::frame::Frame::YUVFrame(f) => Some(FrameInfo {
+                needs_conversion: true,
+                ..frame_info!(f, gst_video::VideoFormat::Nv12)
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn video_info(&self, fps: u32) -> Result<gst_video::VideoInfo, glib::BoolError> {
+        gst_video::VideoInfo::builder(self.gst_v_format, self.width, self.height)
+            .fps(gst::Fraction::new(fps as i32, 1))
+            .interlace_mode(gst_video::VideoInterlaceMode::Progressive)
+            .build()
+    }
+}
+
+/// Name of the backend scap captures with on this platform
+fn capture_backend() -> &'static str {
+    if cfg!(target_os = "linux") {
+        "pipewire"
+    } else if cfg!(target_os = "macos") {
+        "screencapturekit"
+    } else if cfg!(target_os = "windows") {
+        "windows-graphics-capture"
+    } else {
+        "unknown"
+    }
+}
+
+/// Current time on the clock scap timestamps frames with.
+///
+/// On Linux this assumes the PipeWire buffer timestamps scap passes on are
+/// taken from the same monotonic clock as glib's.
+fn capture_clock_now() -> u64 {
+    if cfg!(target_os = "windows") {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+    } else {
+        glib::monotonic_time() as u64 * 1000
+    }
+}
+
+/// Formats scap's PipeWire backend delivers frames in, whatever frame type was
+/// requested
+const PIPEWIRE_FORMATS: [gst_video::VideoFormat; 4] = [
+    gst_video::VideoFormat::Rgbx,
+    gst_video::VideoFormat::Rgb,
+    gst_video::VideoFormat::Xbgr,
+    gst_video::VideoFormat::Bgrx,
+];
+
+/// Converter config placing frames of `in_info` in frames of `out_info`
+/// according to `scaling_mode`
+fn scaling_config(
+    scaling_mode: ScalingMode,
+    in_info: &gst_video::VideoInfo,
+    out_info: &gst_video::VideoInfo,
+) -> Option<gst_video::VideoConverterConfig> {
+    let (in_width, in_height) = (in_info.width() as u64, in_info.height() as u64);
+    let (out_width, out_height) = (out_info.width() as u64, out_info.height() as u64);
+    let in_is_wider = in_width * out_height > in_height * out_width;
+
+    let mut config = gst_video::VideoConverterConfig::new();
+
+    // Frames cut down to even dimensions for `even-dimensions` lose their last
+    // column or row instead of being scaled by a pixel
+    let cut_by_one =
+        |size: u64, out_size: u64| size.checked_sub(out_size).is_some_and(|cut| cut <= 1);
+    if cut_by_one(in_width, out_width) && cut_by_one(in_height, out_height) {
+        config.set_src_width(Some(out_width as i32));
+        config.set_src_height(Some(out_height as i32));
+        return Some(config);
+    }
+
+    match scaling_mode {
+        ScalingMode::Stretch => return None,
+        ScalingMode::Letterbox => {
+            let (width, height) = if in_is_wider {
+                (out_width, in_height * out_width / in_width)
+            } else {
+                (in_width * out_height / in_height, out_height)
+            };
+            config.set_dest_x(((out_width - width) / 2) as i32);
+            config.set_dest_y(((out_height - height) / 2) as i32);
+            config.set_dest_width(Some(width as i32));
+            config.set_dest_height(Some(height as i32));
+            config.set_fill_border(true);
+            config.set_border_argb(0xff000000);
+        }
+        ScalingMode::Crop => {
+            let (width, height) = if in_is_wider {
+                (out_width * in_height / out_height, in_height)
+            } else {
+                (in_width, out_height * in_width / out_width)
+            };
+            config.set_src_x(((in_width - width) / 2) as i32);
+            config.set_src_y(((in_height - height) / 2) as i32);
+            config.set_src_width(Some(width as i32));
+            config.set_src_height(Some(height as i32));
+        }
+    }
+
+    Some(config)
+}
+
+/// Kind, title and scap id of `target`
+fn target_key(target: &scap::Target) -> (TargetKind, &str, u32) {
+    match target {
+        scap::Target::Display(display) => (TargetKind::Display, &display.title, display.id),
+        scap::Target::Window(window) => (TargetKind::Window, &window.title, window.id),
+    }
+}
+
+/// Identifier of `target`.
+///
+/// It's `display:` or `window:` followed by the title of the target and `#`
+/// with the numeric id scap reports, which tells targets with the same title
+/// apart. That id is a handle that can change between sessions, so
+/// `resolve_target()` falls back to the title when it doesn't match.
+fn stable_target_id(target: &scap::Target) -> String {
+    let (kind, title, id) = target_key(target);
+    let kind = match kind {
+        TargetKind::Display => "display",
+        TargetKind::Window => "window",
+    };
+    format!("{kind}:{title}#{id}")
+}
+
+/// All capturable targets, sorted by kind, title and scap id so the order
+/// doesn't depend on how the platform happens to list them
+fn sorted_targets() -> Vec<scap::Target> {
+    let mut targets = scap::get_all_targets();
+    targets.sort_by(|a, b| target_key(a).cmp(&target_key(b)));
+    targets
+}
+
+/// Describe `target` for applications as a structure with its `id` for the
+/// `target` property, `name` and `kind`
+fn target_structure(target: &scap::Target) -> gst::Structure {
+    let (name, kind) = match target {
+        scap::Target::Display(display) => (&display.title, TargetKind::Display),
+        scap::Target::Window(window) => (&window.title, TargetKind::Window),
+    };
+
+    gst::Structure::builder("scapsrc-target")
+        .field("id", stable_target_id(target))
+        .field("name", name)
+        .field("kind", kind)
+        .build()
+}
+
+/// Resolve the `target` property to the target to give scap.
+///
+/// scap captures the primary display without a target on all platforms but
+/// Linux, where the user picks what to share through the portal.
+fn resolve_target(target: &str) -> Result<Option<scap::Target>, gst::ErrorMessage> {
+    if cfg!(target_os = "linux") {
+        return Ok(None);
+    }
+
+    match target {
+        "default" | "primary" => Ok(None),
+        id => {
+            let mut targets = sorted_targets();
+            let ids = targets.iter().map(stable_target_id).collect::<Vec<_>>();
+
+            // Ids persisted in another session may have a stale handle
+            let position = ids
+                .iter()
+                .position(|target_id| target_id == id)
+                .or_else(|| {
+                    ids.iter()
+                        .position(|target_id| without_handle(target_id) == without_handle(id))
+                });
+
+            position
+                .map(|position| Some(targets.swap_remove(position)))
+                .ok_or_else(|| {
+                    gst::error_msg!(
+                        gst::ResourceError::NotFound,
+                        ["No capture target with id `{id}`"]
+                    )
+                })
+        }
+    }
+}
+
+/// A target id without the scap id, only identifying the target by its title
+fn without_handle(id: &str) -> &str {
+    id.rsplit_once('#').map_or(id, |(id, _)| id)
+}
+
+/// Wrap the data of `frame` in a buffer without copying it.
+///
+/// scap hands over the frame data as an owned `Vec`, which the buffer takes
+/// ownership of as is. Captured frames are only copied later on when
+/// converting or scaling them, or when repacking their rows into the default
+/// layout for downstream elements that ignore `GstVideoMeta`.
+fn frame_to_buffer(frame: scap::frame::Frame) -> gst::Buffer {
+    match frame {
+        scap::frame::Frame::RGB(f) => gst::Buffer::from_slice(f.data),
+        scap::frame::Frame::RGBx(f) => gst::Buffer::from_slice(f.data),
+        scap::frame::Frame::XBGR(f) => gst::Buffer::from_slice(f.data),
+        scap::frame::Frame::BGRx(f) => gst::Buffer::from_slice(f.data),
+        scap::frame::Frame::BGR0(f) => gst::Buffer::from_slice(f.data),
+        scap::frame::Frame::BGRA(f) => gst::Buffer::from_slice(f.data),
+        scap::frame::Frame::YUVFrame(f) => gst::Buffer::from_mut_slice(pack_nv12(&f)),
+    }
+}
+
+/// Stride of the rows of a frame of `info` from scap, if it differs from the
+/// default stride of GStreamer.
+///
+/// scap packs rows tightly, while GStreamer pads them to 4 bytes, e.g. for RGB
+/// at widths that aren't a multiple of 4. NV12 frames are already copied into
+/// the default layout.
+fn packed_stride(info: &gst_video::VideoInfo) -> Option<i32> {
+    let stride = info.width() as i32 * info.format_info().pixel_stride()[0];
+    (info.n_planes() == 1 && stride != info.stride()[0]).then_some(stride)
+}
+
+/// Describe the actual layout of `buffer` holding a frame of `info` from scap
+/// with a `GstVideoMeta` if it differs from the default one.
+fn add_packed_video_meta(
+    buffer: &mut gst::BufferRef,
+    info: &gst_video::VideoInfo,
+) -> Result<(), glib::BoolError> {
+    if let Some(stride) = packed_stride(info) {
+        gst_video::VideoMeta::add_full(
+            buffer,
+            gst_video::VideoFrameFlags::empty(),
+            info.format(),
+            info.width(),
+            info.height(),
+            &[0],
+            &[stride],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Copy `buffer` holding a frame of `info` with a `GstVideoMeta` into the
+/// default layout, for downstream elements that ignore the meta.
+fn repack(
+    buffer: gst::Buffer,
+    info: &gst_video::VideoInfo,
+) -> Result<gst::Buffer, glib::BoolError> {
+    let in_frame = gst_video::VideoFrame::from_buffer_readable(buffer, info)
+        .map_err(|_| glib::bool_error!("Failed to map frame"))?;
+    let out_buffer = gst::Buffer::with_size(info.size())?;
+    let mut out_frame = gst_video::VideoFrame::from_buffer_writable(out_buffer, info)
+        .map_err(|_| glib::bool_error!("Failed to map repacked frame"))?;
+    out_frame.copy(&in_frame)?;
+
+    Ok(out_frame.into_buffer())
+}
+
+/// Whether `buffer` holding a frame of `info` from scap is all black.
+///
+/// Only a sparse grid of pixels is checked to keep this cheap, so frames that
+/// are black except for small details count as black too.
+fn is_black_frame(buffer: &gst::BufferRef, info: &gst_video::VideoInfo) -> bool {
+    let Ok(map) = buffer.map_readable() else {
+        return false;
+    };
+
+    let finfo = info.format_info();
+    let stride = packed_stride(info).unwrap_or(info.stride()[0]) as usize;
+    let pixel_stride = finfo.pixel_stride()[0] as usize;
+    // Color components of RGB formats, luma of YUV formats, where black is
+    // at 16
+    let (components, max_level) = match finfo.is_rgb() {
+        true => (&finfo.poffset()[..3], 8),
+        false => (&finfo.poffset()[..1], 16 + 8),
+    };
+
+    (0..BLACK_FRAME_SAMPLES * BLACK_FRAME_SAMPLES).all(|i| {
+        let (col, row) = (i % BLACK_FRAME_SAMPLES, i / BLACK_FRAME_SAMPLES);
+        let x = ((2 * col + 1) * info.width() / (2 * BLACK_FRAME_SAMPLES)) as usize;
+        let y = ((2 * row + 1) * info.height() / (2 * BLACK_FRAME_SAMPLES)) as usize;
+        let pixel = info.offset()[0] + y * stride + x * pixel_stride;
+        components
+            .iter()
+            .all(|&c| map.get(pixel + c as usize).is_some_and(|&v| v <= max_level))
+    })
+}
+
+/// All black frame of the same type and size as `frame`
+fn black_frame_like(frame: &scap::frame::Frame) -> scap::frame::Frame {
+    use scap::frame::Frame;
+
+    let mut black = frame.clone();
+    match &mut black {
+        Frame::RGB(f) => f.data.fill(0),
+        Frame::RGBx(f) => f.data.fill(0),
+        Frame::XBGR(f) => f.data.fill(0),
+        Frame::BGRx(f) => f.data.fill(0),
+        Frame::BGR0(f) => f.data.fill(0),
+        Frame::BGRA(f) => f
+            .data
+            .chunks_exact_mut(4)
+            .for_each(|pixel| pixel.copy_from_slice(&[0, 0, 0, 255])),
+        // Black in limited range, which scap's NV12 frames are in
+        Frame::YUVFrame(f) => {
+            f.luminance_bytes.fill(16);
+            f.chrominance_bytes.fill(128);
+        }
+    }
+
+    black
+}
+
+/// `frame` with its capture time set to `display_time`
+fn with_display_time(mut frame: scap::frame::Frame, display_time: u64) -> scap::frame::Frame {
+    use scap::frame::Frame;
+
+    match &mut frame {
+        Frame::RGB(f) => f.display_time = display_time,
+        Frame::RGBx(f) => f.display_time = display_time,
+        Frame::XBGR(f) => f.display_time = display_time,
+        Frame::BGRx(f) => f.display_time = display_time,
+        Frame::BGR0(f) => f.display_time = display_time,
+        Frame::BGRA(f) => f.display_time = display_time,
+        Frame::YUVFrame(f) => f.display_time = display_time,
+    }
+
+    frame
+}
+
+/// Copy the planes of `frame` into the default NV12 layout of GStreamer
+fn pack_nv12(frame: &scap::frame::YUVFrame) -> Vec<u8> {
+    let width = frame.width as usize;
+    let height = (frame.height as usize + 1) & !1;
+    let stride = (width + 3) & !3;
+
+    let mut data = vec![0; stride * height * 3 / 2];
+    let (luma, chroma) = data.split_at_mut(stride * height);
+    let planes = [
+        (luma, &frame.luminance_bytes, frame.luminance_stride),
+        (chroma, &frame.chrominance_bytes, frame.chrominance_stride),
+    ];
+    for (dst, src, src_stride) in planes {
+        for (dst_row, src_row) in dst
+            .chunks_exact_mut(stride)
+            .zip(src.chunks(src_stride.max(1) as usize))
+        {
+            let len = dst_row.len().min(src_row.len());
+            dst_row[..len].copy_from_slice(&src_row[..len]);
+        }
+    }
+
+    data
 }
 
+#[derive(Clone)]
 struct Settings {
     pub show_cursor: bool,
     pub fps: u32,
     pub perform_internal_preroll: bool,
+    pub duration: Option<gst::ClockTime>,
+    pub output_format: OutputFormat,
+    pub startup_delay_ms: u32,
+    pub emit_signals: bool,
+    pub max_fps: u32,
+    pub heartbeat_interval: u32,
+    pub auto_convert: bool,
+    pub max_retries: u32,
+    pub output_width: u32,
+    pub output_height: u32,
+    pub paused: bool,
+    pub crop_x: u32,
+    pub crop_y: u32,
+    pub crop_width: u32,
+    pub crop_height: u32,
+    pub scaling_mode: ScalingMode,
+    pub freeze_on_pause: bool,
+    pub on_unsupported_format: UnsupportedFormatAction,
+    pub adaptive_fps: bool,
+    pub min_fps: u32,
+    pub target: String,
+    pub fixed_output_size: bool,
+    pub start_retries: u32,
+    pub start_retry_delay_ms: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub smooth_timestamps: bool,
+    pub ts_offset: i64,
+    pub deterministic_timing: bool,
+    pub detect_black_frames: bool,
+    pub black_frame_threshold: u32,
+    pub even_dimensions: bool,
+    pub fallback_on_stall: bool,
+    pub stall_timeout_ms: u32,
     // pub sel_target_cb: Option<glib::Closure>,
 }
 
@@ -68,464 +527,4158 @@ impl Default for Settings {
             show_cursor: DEFAULT_SHOW_CURSOR,
             fps: DEFAULT_FPS,
             perform_internal_preroll: DEFAULT_PERFORM_INTERNAL_PREROLL,
+            duration: DEFAULT_DURATION,
+            output_format: DEFAULT_OUTPUT_FORMAT,
+            startup_delay_ms: DEFAULT_STARTUP_DELAY_MS,
+            emit_signals: DEFAULT_EMIT_SIGNALS,
+            max_fps: DEFAULT_MAX_FPS,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            auto_convert: DEFAULT_AUTO_CONVERT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            output_width: DEFAULT_OUTPUT_WIDTH,
+            output_height: DEFAULT_OUTPUT_HEIGHT,
+            paused: DEFAULT_PAUSED,
+            crop_x: DEFAULT_CROP_X,
+            crop_y: DEFAULT_CROP_Y,
+            crop_width: DEFAULT_CROP_WIDTH,
+            crop_height: DEFAULT_CROP_HEIGHT,
+            scaling_mode: DEFAULT_SCALING_MODE,
+            freeze_on_pause: DEFAULT_FREEZE_ON_PAUSE,
+            on_unsupported_format: DEFAULT_ON_UNSUPPORTED_FORMAT,
+            adaptive_fps: DEFAULT_ADAPTIVE_FPS,
+            min_fps: DEFAULT_MIN_FPS,
+            target: DEFAULT_TARGET.to_string(),
+            fixed_output_size: DEFAULT_FIXED_OUTPUT_SIZE,
+            start_retries: DEFAULT_START_RETRIES,
+            start_retry_delay_ms: DEFAULT_START_RETRY_DELAY_MS,
+            max_width: DEFAULT_MAX_WIDTH,
+            max_height: DEFAULT_MAX_HEIGHT,
+            smooth_timestamps: DEFAULT_SMOOTH_TIMESTAMPS,
+            ts_offset: DEFAULT_TS_OFFSET,
+            deterministic_timing: DEFAULT_DETERMINISTIC_TIMING,
+            detect_black_frames: DEFAULT_DETECT_BLACK_FRAMES,
+            black_frame_threshold: DEFAULT_BLACK_FRAME_THRESHOLD,
+            even_dimensions: DEFAULT_EVEN_DIMENSIONS,
+            fallback_on_stall: DEFAULT_FALLBACK_ON_STALL,
+            stall_timeout_ms: DEFAULT_STALL_TIMEOUT_MS,
             // sel_target_cb: None,
         }
     }
 }
 
-#[derive(Default)]
-struct State {
-    info: Option<gst_video::VideoInfo>,
-    width: i32,
-    height: i32,
-    base_time: u64,
-}
-
-pub struct ScapSrc {
-    settings: Mutex<Settings>,
-    capturer: Mutex<Option<Capturer>>,
-    state: Mutex<State>,
-}
-
-impl Default for ScapSrc {
-    fn default() -> Self {
-        Self {
-            settings: Mutex::new(Default::default()),
-            capturer: Mutex::new(None),
-            state: Mutex::new(Default::default()),
+impl Settings {
+    /// The framerate frames are delivered at, taking `max-fps` into account
+    fn output_fps(&self) -> u32 {
+        match self.max_fps {
+            0 => self.fps,
+            max_fps => self.fps.min(max_fps),
         }
     }
-}
 
-impl ScapSrc {
-    fn ensure_correct_format(&self, frame_info: &FrameInfo) -> Result<(), gst::FlowError> {
-        let state = self.state.lock().unwrap();
+    fn output_sizing(&self) -> OutputSizing {
+        OutputSizing {
+            width: self.output_width,
+            height: self.output_height,
+            max_width: self.max_width,
+            max_height: self.max_height,
+            even_dimensions: self.even_dimensions,
+        }
+    }
 
-        let info = match &state.info {
-            Some(i) => i,
-            None => return Err(gst::FlowError::NotNegotiated),
-        };
+    /// The size frames captured at `width`x`height` are delivered at
+    fn output_size_for(&self, width: u32, height: u32) -> (u32, u32) {
+        self.output_sizing().size_for(width, height)
+    }
 
-        if (state.width, state.height) != (frame_info.width as i32, frame_info.height as i32)
-            || info.format() != frame_info.gst_v_format
-        {
-            gst::debug!(
-                CAT,
-                imp = self,
-                "Resolutions differ. Will try to renegotiate"
-            );
+    /// Copy of what `create()` needs, so the settings aren't cloned for
+    /// every frame
+    fn frame_settings(&self) -> FrameSettings {
+        FrameSettings {
+            fps: self.output_fps(),
+            min_fps: self.min_fps,
+            max_fps: self.max_fps,
+            duration: self.duration,
+            output_format: self.output_format,
+            emit_signals: self.emit_signals,
+            heartbeat_interval: self.heartbeat_interval,
+            auto_convert: self.auto_convert,
+            max_retries: self.max_retries,
+            sizing: self.output_sizing(),
+            scaling_mode: self.scaling_mode,
+            on_unsupported_format: self.on_unsupported_format,
+            adaptive_fps: self.adaptive_fps,
+            fixed_output_size: self.fixed_output_size,
+            smooth_timestamps: self.smooth_timestamps,
+            ts_offset: self.ts_offset,
+            deterministic_timing: self.deterministic_timing,
+            detect_black_frames: self.detect_black_frames,
+            black_frame_threshold: self.black_frame_threshold,
+            paused: self.paused,
+            stall_timeout: self
+                .fallback_on_stall
+                .then(|| Duration::from_millis(self.stall_timeout_ms.into())),
+        }
+    }
 
-            let new_video_info = gst_video::VideoInfo::builder(
-                frame_info.gst_v_format,
-                frame_info.width,
-                frame_info.height,
-            )
-            .build()
-            .map_err(|err| {
-                gst::error!(CAT, imp = self, "Failed to create video info: {err}");
-                gst::FlowError::Error
-            })?;
+    /// The area of the target to capture, `None` captures all of it
+    fn crop_area(&self) -> Option<scap::capturer::Area> {
+        if self.crop_width == 0 || self.crop_height == 0 {
+            return None;
+        }
 
-            let new_caps = new_video_info.to_caps().map_err(|err| {
-                gst::error!(CAT, imp = self, "Failed to create caps: {err}");
-                gst::FlowError::Error
-            })?;
+        Some(scap::capturer::Area {
+            origin: scap::capturer::Point {
+                x: self.crop_x.into(),
+                y: self.crop_y.into(),
+            },
+            size: scap::capturer::Size {
+                width: self.crop_width.into(),
+                height: self.crop_height.into(),
+            },
+        })
+    }
 
-            // Deadlock prevention
-            drop(state);
+    /// The smallest scap resolution preset covering `output-width` and
+    /// `output-height`, so scap does as much of the downscaling as it can. The
+    /// rest is done in `create()`.
+    ///
+    /// Presets set the width and keep the aspect ratio of the target, which
+    /// isn't known before capturing. `output-height` is covered for targets up
+    /// to 16:9, wider ones are scaled up to it in `create()`.
+    fn scap_resolution(&self) -> scap::capturer::Resolution {
+        use scap::capturer::Resolution;
 
-            if let Err(err) = self.obj().set_caps(&new_caps) {
-                gst::error!(CAT, imp = self, "Failed to set caps: {err}");
-                return Err(gst::FlowError::Error);
-            }
+        let width_for_height = (u64::from(self.output_height) * 16).div_ceil(9);
+        match u64::from(self.output_width).max(width_for_height) {
+            0 => Resolution::Captured,
+            1..=640 => Resolution::_480p,
+            641..=1280 => Resolution::_720p,
+            1281..=1920 => Resolution::_1080p,
+            1921..=2560 => Resolution::_1440p,
+            2561..=3840 => Resolution::_2160p,
+            3841..=7680 => Resolution::_4320p,
+            _ => Resolution::Captured,
         }
+    }
 
-        Ok(())
+    fn capturer_options(&self) -> Result<scap::capturer::Options, gst::ErrorMessage> {
+        // TODO: Scale the cursor along with `output-width`/`output-height`. scap
+        // has no option for the cursor size and doesn't expose the cursor
+        // image, so it can't be composited here either
+        Ok(scap::capturer::Options {
+            fps: self.fps,
+            show_cursor: self.show_cursor,
+            show_highlight: true,
+            target: resolve_target(&self.target)?,
+            crop_area: self.crop_area(),
+            output_type: self.output_format.frame_type(),
+            output_resolution: self.scap_resolution(),
+            excluded_targets: None,
+        })
     }
 }
 
-#[glib::object_subclass]
-impl ObjectSubclass for ScapSrc {
-    const NAME: &'static str = "ScapSrc";
-    type Type = super::ScapSrc;
-    type ParentType = gst_base::PushSrc;
+/// The settings the output size depends on
+#[derive(Clone, Copy)]
+struct OutputSizing {
+    width: u32,
+    height: u32,
+    max_width: u32,
+    max_height: u32,
+    even_dimensions: bool,
 }
 
-impl ObjectImpl for ScapSrc {
-    fn properties() -> &'static [glib::ParamSpec] {
-        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
-            vec![
-                glib::ParamSpecUInt::builder("fps")
-                    .nick("Frames per second")
-                    .blurb("Rate to capture screen at")
-                    .minimum(1)
-                    .default_value(DEFAULT_FPS)
-                    .mutable_ready()
-                    .build(),
-                glib::ParamSpecBoolean::builder("show-cursor")
-                    .nick("Show cursor")
-                    .blurb("Whether to capture the cursor or not")
-                    .default_value(DEFAULT_SHOW_CURSOR)
-                    .mutable_ready()
-                    .build(),
-                glib::ParamSpecBoolean::builder("perform-internal-preroll")
-                    .nick("Perform internal preroll")
-                    .blurb("Pull one frame from the capture source before format negotiation")
-                    .default_value(DEFAULT_PERFORM_INTERNAL_PREROLL)
-                    .mutable_ready()
-                    .build(),
-                // glib::ParamSpecBoxed::builder::<Option<glib::Closure>>("select-target-cb")
-                //     .nick("Select target callback")
-                //     .blurb("Function that accepts a list of targets and returns the target that should be captured")
-                //     .mutable_ready()
-                //     .build(),
-            ]
-        });
+impl OutputSizing {
+    /// The size frames captured at `width`x`height` are delivered at
+    fn size_for(&self, width: u32, height: u32) -> (u32, u32) {
+        // The dimension that isn't set follows the aspect ratio of the frame
+        let scaled = |size: u32, to: u32, from: u32| {
+            let from = from.max(1) as u64;
+            ((size as u64 * to as u64 + from / 2) / from).clamp(1, i32::MAX as u64) as u32
+        };
+        let (width, height) = match (self.width, self.height) {
+            (0, 0) => (width, height),
+            (0, output_height) => (scaled(width, output_height, height), output_height),
+            (output_width, 0) => (output_width, scaled(height, output_width, width)),
+            output_size => output_size,
+        };
 
-        &PROPERTIES
+        let (width, height) = self.clamp_to_max_size(width, height);
+        match self.even_dimensions {
+            true => ((width & !1).max(2), (height & !1).max(2)),
+            false => (width, height),
+        }
     }
 
-    fn constructed(&self) {
-        self.parent_constructed();
+    /// Scale `width`x`height` down to fit within `max-width` and `max-height`,
+    /// keeping the aspect ratio
+    fn clamp_to_max_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let max_width = Some(self.max_width).filter(|&w| w > 0).unwrap_or(u32::MAX);
+        let max_height = Some(self.max_height).filter(|&h| h > 0).unwrap_or(u32::MAX);
+        if width <= max_width && height <= max_height {
+            return (width, height);
+        }
 
-        let obj = self.obj();
-        obj.set_live(true);
-        obj.set_format(gst::Format::Time);
+        let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+        let scaled = |size: u32| ((size as f64 * scale) as u32).max(1);
+
+        (scaled(width), scaled(height))
     }
 
-    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
-        match pspec.name() {
-            "fps" => {
-                let mut settings = self.settings.lock().unwrap();
-                let new_fps = value.get().expect("type checked upstream");
+    /// Whether frames may have to be scaled to be delivered
+    fn is_set(&self) -> bool {
+        self.width > 0
+            || self.height > 0
+            || self.max_width > 0
+            || self.max_height > 0
+            || self.even_dimensions
+    }
+}
 
-                gst::info!(
-                    CAT,
-                    imp = self,
-                    "fps was changed from `{}` to `{}`",
-                    settings.fps,
-                    new_fps
-                );
+/// The settings `create()` needs for a frame
+#[derive(Clone, Copy)]
+struct FrameSettings {
+    /// The output fps
+    fps: u32,
+    min_fps: u32,
+    max_fps: u32,
+    duration: Option<gst::ClockTime>,
+    output_format: OutputFormat,
+    emit_signals: bool,
+    heartbeat_interval: u32,
+    auto_convert: bool,
+    max_retries: u32,
+    sizing: OutputSizing,
+    scaling_mode: ScalingMode,
+    on_unsupported_format: UnsupportedFormatAction,
+    adaptive_fps: bool,
+    fixed_output_size: bool,
+    smooth_timestamps: bool,
+    ts_offset: i64,
+    deterministic_timing: bool,
+    detect_black_frames: bool,
+    black_frame_threshold: u32,
+    paused: bool,
+    /// How long to wait for a frame before delivering a black one, if
+    /// `fallback-on-stall` is set
+    stall_timeout: Option<Duration>,
+}
 
-                settings.fps = new_fps;
-            }
-            "show-cursor" => {
-                let mut settings = self.settings.lock().unwrap();
-                let new_show_cursor = value.get().expect("type checked upstream");
+impl FrameSettings {
+    fn output_video_info(
+        &self,
+        frame_info: &FrameInfo,
+    ) -> Result<gst_video::VideoInfo, glib::BoolError> {
+        let (width, height) = self.sizing.size_for(frame_info.width, frame_info.height);
+        gst_video::VideoInfo::builder(frame_info.gst_v_format, width, height)
+            .fps(gst::Fraction::new(self.fps as i32, 1))
+            .interlace_mode(gst_video::VideoInterlaceMode::Progressive)
+            .build()
+    }
+}
 
-                gst::info!(
-                    CAT,
-                    imp = self,
-                    "show-cursor was changed from `{}` to `{}`",
-                    settings.show_cursor,
-                    new_show_cursor
-                );
+#[derive(Default)]
+struct State {
+    info: Option<gst_video::VideoInfo>,
+    width: i32,
+    height: i32,
+    /// Capture time of pts 0, latched from the first frame. Negative once
+    /// rebased after the capture clock was reset to a time before the next pts
+    base_time: Option<i64>,
+    qos_earliest_time: Option<gst::ClockTime>,
+    /// Rate downstream processes frames at relative to real time, from the
+    /// last QoS event
+    qos_proportion: Option<f64>,
+    dropped: u64,
+    /// Delay of the last `start()`, waiting for the pipeline to play
+    pending_startup_delay: Option<Duration>,
+    startup_deadline: Option<Instant>,
+    last_pts: Option<u64>,
+    frame_count: u64,
+    last_heartbeat_pts: u64,
+    /// Converter from the captured to the negotiated format with its input and
+    /// output info
+    converter: Option<(
+        gst_video::VideoInfo,
+        gst_video::VideoInfo,
+        gst_video::VideoConverter,
+    )>,
+    last_capture_time: Option<u64>,
+    /// Rolling average of the interval between frames received from scap
+    avg_capture_interval: Option<u64>,
+    /// Rolling average of the time from capturing a frame to receiving it
+    capture_latency: Option<u64>,
+    /// Capture latency the pipeline was last told about
+    reported_capture_latency: u64,
+    /// When a latency message was last posted for the capture latency
+    latency_message_time: Option<Instant>,
+    capture_backend: Option<&'static str>,
+    /// Output info caps were last successfully requested for
+    requested_info: Option<gst_video::VideoInfo>,
+    /// Frames were discarded while paused, or the preroll frame was delivered,
+    /// and timestamps need to be rebased
+    resume_pending: bool,
+    target_resolution: Option<(u32, u32)>,
+    /// The capturer is being built on another thread
+    starting: bool,
+    /// Counts `start()` and `stop()` calls, so the thread building the
+    /// capturer can tell it was stopped or started again meanwhile
+    generation: u64,
+    playing: bool,
+    /// Paused with `freeze-on-pause`, so the frames captured meanwhile are
+    /// dropped once playing again
+    frozen: bool,
+    /// Downstream supports `GstVideoMeta` on buffers
+    video_meta_supported: bool,
+    /// Pts of the frames delivered within the last second
+    recent_pts: VecDeque<u64>,
+    tags_sent: bool,
+    target_kind: Option<TargetKind>,
+    /// Rate `adaptive-fps` currently limits delivery to
+    adaptive_fps: Option<u32>,
+    /// Pts the next frame is due at to stay below `max-fps`
+    throttle_deadline: Option<u64>,
+    /// Rolling average of the time spent in `create()` after getting a frame
+    avg_processing_time: Option<u64>,
+    /// Output size latched from the first frame with `fixed-output-size`
+    fixed_output_size: Option<(u32, u32)>,
+    /// Frames pulled from scap to be delivered, including the ones dropped
+    /// later. Frames captured during the startup delay or while paused are
+    /// never meant to be delivered and not counted
+    captured_frames: u64,
+    /// Instant and pts `deterministic-timing` paces delivery from
+    pacing_start: Option<(Instant, u64)>,
+    /// All black frames delivered in a row
+    black_frames: u32,
+    /// Capture parameters shared with the pipeline as `CAPTURE_CONTEXT_TYPE`
+    context: Option<gst::Context>,
+    /// The negotiated caps, for the samples of `try_pull_frame()`
+    caps: Option<gst::Caps>,
+    /// Black frame with the format and size of the last captured one, which
+    /// `fallback-on-stall` delivers while no frames arrive
+    stall_frame: Option<((gst_video::VideoFormat, u32, u32), scap::frame::Frame)>,
+}
 
-                settings.show_cursor = new_show_cursor;
+impl State {
+    fn record_delivery(&mut self, pts: u64) {
+        self.recent_pts.push_back(pts);
+        while let Some(&first) = self.recent_pts.front() {
+            if pts.saturating_sub(first) <= gst::ClockTime::SECOND.nseconds() {
+                break;
             }
-            "perform-internal-preroll" => {
-                let mut settings = self.settings.lock().unwrap();
-                let new_perf_internal_preroll = value.get().expect("type checked upstream");
-
-                gst::info!(
-                    CAT,
-                    imp = self,
-                    "perform-internal-preroll was changed from `{}` to `{}`",
-                    settings.perform_internal_preroll,
-                    new_perf_internal_preroll,
-                );
+            self.recent_pts.pop_front();
+        }
+    }
 
-                settings.perform_internal_preroll = new_perf_internal_preroll;
+    /// Rate frames were delivered at over the last second
+    fn actual_fps(&self) -> f64 {
+        match (self.recent_pts.front(), self.recent_pts.back()) {
+            (Some(first), Some(last)) if last > first => {
+                (self.recent_pts.len() - 1) as f64 * gst::ClockTime::SECOND.nseconds() as f64
+                    / (last - first) as f64
             }
-            // "select-target-cb" => {
-            //     let mut settings = self.settings.lock().unwrap();
-            //     let new_cb = value.get().expect("type checked upstream");
+            _ => 0.0,
+        }
+    }
 
-            //     gst::info!(CAT, imp = self, "Changing select-target-cb");
+    /// Latch the base time so a frame captured at `capture_time` follows the
+    /// last delivered frame
+    fn rebase(&mut self, capture_time: u64, fps: u32) {
+        let next_pts = self.last_pts.map_or(0, |last_pts| {
+            last_pts + gst::ClockTime::SECOND.nseconds() / fps as u64
+        });
+        self.base_time = Some(capture_time as i64 - next_pts as i64);
+    }
 
-            //     settings.sel_target_cb = new_cb;
-            // }
-            _ => unimplemented!(),
+    /// Pts of a frame captured at `capture_time`.
+    ///
+    /// The base time is latched from the first frame. A frame older than the
+    /// base time, e.g. after the capture clock was reset, rebases it so the
+    /// frame follows the last delivered one.
+    fn frame_pts(&mut self, capture_time: u64, fps: u32) -> u64 {
+        let base_time = *self.base_time.get_or_insert(capture_time as i64);
+        if (capture_time as i64) < base_time {
+            self.rebase(capture_time, fps);
         }
+
+        (capture_time as i64 - self.base_time.unwrap_or(base_time)) as u64
     }
 
-    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
-        match pspec.name() {
-            "fps" => {
-                let settings = self.settings.lock().unwrap();
-                settings.fps.to_value()
-            }
-            "show-cursor" => {
-                let settings = self.settings.lock().unwrap();
-                settings.show_cursor.to_value()
-            }
-            "perform-internal-preroll" => {
-                let settings = self.settings.lock().unwrap();
-                settings.perform_internal_preroll.to_value()
+    /// Snap `pts` to the nearest point of a 1/`fps` grid, or the point after
+    /// the last delivered frame if that one took it already. Dropped frames
+    /// show up as skipped points.
+    fn snap_pts(&self, pts: u64, fps: u32) -> u64 {
+        let frame_duration = gst::ClockTime::SECOND.nseconds() / fps as u64;
+        let snapped = (pts + frame_duration / 2) / frame_duration * frame_duration;
+        match self.last_pts {
+            Some(last_pts) if snapped <= last_pts => last_pts + frame_duration,
+            _ => snapped,
+        }
+    }
+
+    /// Whether the pipeline should query the latency again, as the measured
+    /// capture latency moved by more than a tenth from what was reported.
+    ///
+    /// Every query recalculates the latency of the whole pipeline, so this is
+    /// true at most every `LATENCY_MESSAGE_INTERVAL`.
+    fn capture_latency_changed(&mut self, now: Instant) -> bool {
+        let Some(latency) = self.capture_latency else {
+            return false;
+        };
+
+        let threshold =
+            (self.reported_capture_latency / 10).max(gst::ClockTime::MSECOND.nseconds());
+        if latency.abs_diff(self.reported_capture_latency) <= threshold
+            || self
+                .latency_message_time
+                .is_some_and(|time| now < time + LATENCY_MESSAGE_INTERVAL)
+        {
+            return false;
+        }
+
+        self.reported_capture_latency = latency;
+        self.latency_message_time = Some(now);
+        true
+    }
+
+    /// Whether the caps in place fit frames of `new_info`, either as they were
+    /// last requested or as negotiated. `None` before caps were negotiated.
+    fn caps_fit(&mut self, new_info: &gst_video::VideoInfo, auto_convert: bool) -> Option<bool> {
+        let info = self.info.as_ref()?;
+
+        // Compare against what was last requested as well, as the negotiated
+        // caps can differ from it with `auto-convert`
+        if self.requested_info.as_ref() == Some(new_info) {
+            return Some(true);
+        }
+
+        let fit = (self.width, self.height) == (new_info.width() as i32, new_info.height() as i32)
+            && (info.format() == new_info.format() || auto_convert)
+            && info.fps() == new_info.fps();
+        if fit {
+            self.requested_info = Some(new_info.clone());
+        }
+
+        Some(fit)
+    }
+
+    /// Whether the frame with `pts` is delivered when limiting delivery to
+    /// `max_fps`.
+    ///
+    /// Frames are due on a 1/`max_fps` grid, with half an interval of slack so
+    /// jitter doesn't drop frames arriving at about `max_fps`. The grid starts
+    /// over from `pts` once delivery fell more than an interval behind.
+    fn throttle(&mut self, pts: u64, max_fps: u32) -> bool {
+        let interval = gst::ClockTime::SECOND.nseconds() / max_fps as u64;
+        self.throttle_deadline = Some(match self.throttle_deadline {
+            Some(deadline) if pts + interval / 2 < deadline => return false,
+            Some(deadline) if pts < deadline + interval => deadline + interval,
+            _ => pts + interval,
+        });
+
+        true
+    }
+
+    /// Pts of the next frame with `deterministic-timing`, which only depends
+    /// on the number of frames delivered so far
+    fn counter_pts(&self, fps: u32) -> u64 {
+        self.frame_count * gst::ClockTime::SECOND.nseconds() / fps as u64
+    }
+
+    /// When the frame with `pts` is due with `deterministic-timing`. Pacing
+    /// starts over from `now` if delivery fell more than a frame behind, e.g.
+    /// after pausing or while scap delivers slower than `fps`.
+    fn pacing_deadline(&mut self, pts: u64, fps: u32, now: Instant) -> Instant {
+        let frame_duration = Duration::from_nanos(gst::ClockTime::SECOND.nseconds() / fps as u64);
+        let deadline = self
+            .pacing_start
+            .map(|(start, start_pts)| start + Duration::from_nanos(pts.saturating_sub(start_pts)));
+        match deadline {
+            Some(deadline) if deadline + frame_duration >= now => deadline,
+            _ => {
+                self.pacing_start = Some((now, pts));
+                now
             }
-            // "select-target-cb" => {
-            //     let settings = self.settings.lock().unwrap();
-            //     settings.sel_target_cb.to_value()
-            // }
-            _ => unimplemented!(),
         }
     }
 }
 
-impl GstObjectImpl for ScapSrc {}
+/// Captured frame or the error scap failed to get it with
+type FrameResult = Result<scap::frame::Frame, mpsc::RecvError>;
 
-impl ElementImpl for ScapSrc {
-    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
-        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
-            gst::subclass::ElementMetadata::new(
-                "Scap screencast source",
-                "Source/Video",
-                "Scap screencast source",
-                "Marcus L. Hanestad <marlhan@proton.me>",
-            )
-        });
+/// Frame taken from the `FrameQueue`
+struct QueuedFrame {
+    frame: FrameResult,
+    /// Frames dropped before this one as `create()` fell behind
+    dropped: u64,
+    /// Pulled for the internal preroll instead of by the capture thread
+    preroll: bool,
+}
 
-        Some(&*ELEMENT_METADATA)
+/// Frames from the capture thread waiting for `create()`
+#[derive(Default)]
+struct Frames {
+    /// Frame pulled for the internal preroll, taken before the queued ones
+    preroll: Option<scap::frame::Frame>,
+    queue: VecDeque<FrameResult>,
+    /// Frames dropped since the last one was taken
+    dropped: u64,
+    /// The configured duration was reached, so `create()` ends the stream
+    /// instead of waiting for frames
+    eos: bool,
+    /// `create()` was unlocked and must not wait for frames
+    flushing: bool,
+}
+
+/// Hands frames from the capture thread to `create()`, which waits on
+/// `frame_added` until one arrives or it's unlocked
+#[derive(Default)]
+struct FrameQueue {
+    frames: Mutex<Frames>,
+    frame_added: Condvar,
+}
+
+impl FrameQueue {
+    /// Queue `frame`, dropping the oldest one if `create()` falls behind
+    fn push(&self, frame: FrameResult) {
+        let mut frames = self.frames.lock().unwrap();
+        if frames.queue.len() == MAX_QUEUED_FRAMES {
+            gst::trace!(CAT, "Dropping oldest queued frame");
+            frames.queue.pop_front();
+            frames.dropped += 1;
+        }
+        frames.queue.push_back(frame);
+        self.frame_added.notify_all();
     }
 
-    fn pad_templates() -> &'static [gst::PadTemplate] {
-        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
-            let caps = gst_video::VideoCapsBuilder::new()
-                .format_list([
-                    gst_video::VideoFormat::Rgb,
-                    gst_video::VideoFormat::Rgbx,
-                    gst_video::VideoFormat::Xbgr,
-                    gst_video::VideoFormat::Bgrx,
-                    gst_video::VideoFormat::Bgrx,
-                    gst_video::VideoFormat::Bgra,
-                ])
-                .build();
-            let src_pad_template = gst::PadTemplate::new(
-                "src",
-                gst::PadDirection::Src,
-                gst::PadPresence::Always,
-                &caps,
-            )
-            .unwrap();
+    /// Queue the frame pulled for the internal preroll, delivered before the
+    /// ones from the capture thread
+    fn push_preroll(&self, frame: scap::frame::Frame) {
+        self.frames.lock().unwrap().preroll = Some(frame);
+        self.frame_added.notify_all();
+    }
 
-            vec![src_pad_template]
-        });
+    /// Wait up to `timeout` for the next frame.
+    ///
+    /// Fails with `Flushing` once unlocked and with `Eos` once the duration
+    /// was reached. Returns `None` if no frame arrived in time.
+    fn pop(&self, timeout: Option<Duration>) -> Result<Option<QueuedFrame>, gst::FlowError> {
+        let frames = self.frames.lock().unwrap();
+        let waiting = |frames: &mut Frames| {
+            !frames.flushing && !frames.eos && frames.preroll.is_none() && frames.queue.is_empty()
+        };
+        let mut frames = match timeout {
+            Some(timeout) => {
+                self.frame_added
+                    .wait_timeout_while(frames, timeout, waiting)
+                    .unwrap()
+                    .0
+            }
+            None => self.frame_added.wait_while(frames, waiting).unwrap(),
+        };
+        if frames.flushing {
+            return Err(gst::FlowError::Flushing);
+        }
+        if frames.eos {
+            return Err(gst::FlowError::Eos);
+        }
+        if let Some(frame) = frames.preroll.take() {
+            return Ok(Some(QueuedFrame {
+                frame: Ok(frame),
+                dropped: 0,
+                preroll: true,
+            }));
+        }
 
-        &PAD_TEMPLATES
+        Ok(frames.queue.pop_front().map(|frame| QueuedFrame {
+            frame,
+            dropped: std::mem::take(&mut frames.dropped),
+            preroll: false,
+        }))
     }
 
-    fn change_state(
-        &self,
-        transition: gst::StateChange,
-    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
-        gst::debug!(CAT, imp = self, "State transition: {transition:?}");
+    /// End the stream instead of waiting for the next frame
+    fn set_eos(&self) {
+        self.frames.lock().unwrap().eos = true;
+        self.frame_added.notify_all();
+    }
 
-        let mut res = self.parent_change_state(transition)?;
+    fn set_flushing(&self, flushing: bool) {
+        self.frames.lock().unwrap().flushing = flushing;
+        self.frame_added.notify_all();
+    }
 
-        match transition {
-            gst::StateChange::NullToReady => {}
-            gst::StateChange::ReadyToPaused => res = gst::StateChangeSuccess::NoPreroll,
-            gst::StateChange::PausedToPlaying => {
-                let mut capturer = self.capturer.lock().unwrap();
-                match &mut *capturer {
-                    Some(c) => c.start_capture(),
-                    None => {
-                        gst::error!(CAT, imp = self, "Capturer is missing");
-                        return Err(gst::StateChangeError);
+    fn is_flushing(&self) -> bool {
+        self.frames.lock().unwrap().flushing
+    }
+
+    /// Drop all queued frames, without counting them as dropped
+    fn clear(&self) {
+        let mut frames = self.frames.lock().unwrap();
+        frames.queue.clear();
+        frames.dropped = 0;
+    }
+
+    /// Drop all queued frames and stop flushing
+    fn reset(&self) {
+        *self.frames.lock().unwrap() = Frames::default();
+    }
+}
+
+/// What a `CaptureThread` gets frames from, a `Capturer` outside of tests
+trait FrameSource: Send + 'static {
+    /// Block until the next frame arrives
+    fn next_frame(&self) -> FrameResult;
+    fn stop(&mut self);
+}
+
+impl FrameSource for Capturer {
+    fn next_frame(&self) -> FrameResult {
+        self.get_next_frame()
+    }
+
+    fn stop(&mut self) {
+        self.stop_capture();
+    }
+}
+
+/// Gets the frames of a running capturer on a thread of its own, so
+/// `create()` can stop waiting for them when unlocked.
+///
+/// `get_next_frame()` can't be interrupted, and the capturer can only be
+/// stopped once it returns. Stopping waits for that up to
+/// `CAPTURE_STOP_TIMEOUT`, e.g. for PipeWire, which delivers no frames while
+/// the screen doesn't change. After that the thread is abandoned and stops
+/// the capturer itself once its frame arrives.
+///
+/// Stopping a capturer ends all capturing of the process on Linux (see
+/// `ScapSrc::restart_capturer()`), so an abandoned capturer doesn't stop
+/// anymore once another one was built. Its capture ends along with that
+/// one's.
+struct CaptureThread {
+    stop: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+    /// Disconnected once the thread is done
+    done: mpsc::Receiver<()>,
+}
+
+impl CaptureThread {
+    /// Queue the frames of the started `source` in `frames`
+    fn spawn(mut source: impl FrameSource, frames: Arc<FrameQueue>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let built = CAPTURERS_BUILT.load(Ordering::SeqCst);
+        let (done_tx, done) = mpsc::channel();
+        let handle = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let _done = done_tx;
+
+                while !stop.load(Ordering::SeqCst) {
+                    let frame = source.next_frame();
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let failed = frame.is_err();
+                    frames.push(frame);
+                    if failed {
+                        break;
                     }
                 }
-                gst::info!(CAT, imp = self, "Capturing engine was started");
+
+                // Would end the capture of the newer capturer as well
+                if cfg!(target_os = "linux") && CAPTURERS_BUILT.load(Ordering::SeqCst) != built {
+                    return;
+                }
+                source.stop();
             }
-            gst::StateChange::PlayingToPaused => {}
-            gst::StateChange::PausedToReady => {}
-            gst::StateChange::ReadyToNull => {}
-            gst::StateChange::NullToNull => {}
-            gst::StateChange::ReadyToReady => {}
-            gst::StateChange::PausedToPaused => {}
-            gst::StateChange::PlayingToPlaying => {}
+        });
+
+        Self { stop, handle, done }
+    }
+
+    /// Stop the capturer, returning once it's stopped or abandoned after
+    /// `CAPTURE_STOP_TIMEOUT`
+    fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        let res = self.done.recv_timeout(CAPTURE_STOP_TIMEOUT);
+        if res == Err(mpsc::RecvTimeoutError::Timeout) {
+            gst::debug!(
+                CAT,
+                "No frame arrived to stop the capturer at, abandoning it"
+            );
+            return;
         }
 
-        Ok(res)
+        if self.handle.join().is_err() {
+            gst::error!(CAT, "Capture thread panicked");
+        }
+    }
+
+    /// Stop the capturer once its next frame arrives, without waiting for it
+    fn abandon(self) {
+        self.stop.store(true, Ordering::SeqCst);
     }
 }
 
-impl BaseSrcImpl for ScapSrc {
-    fn start(&self) -> Result<(), gst::ErrorMessage> {
-        let mut capturer = self.capturer.lock().unwrap();
-        let settings = self.settings.lock().unwrap();
+/// The capturer of the element once built
+enum Capture {
+    /// Not capturing yet
+    Idle(Capturer),
+    Running(CaptureThread),
+}
 
-        if let Some(mut capturer) = capturer.take() {
-            gst::debug!(CAT, imp = self, "Capturer exists, stopping");
-            capturer.stop_capture();
+impl Capture {
+    /// Start capturing into `frames` if not already
+    fn start(self, frames: &Arc<FrameQueue>) -> Self {
+        match self {
+            Capture::Idle(mut capturer) => {
+                capturer.start_capture();
+                Capture::Running(CaptureThread::spawn(capturer, frames.clone()))
+            }
+            running => running,
         }
+    }
 
-        // TODO: Use settings.sel_target_cb to select the target
-        // let targets = scap::get_all_targets();
-        // if targets.is_empty() {
-        //     return Err(gst::error_msg!(gst::LibraryError::Init, [
-        //         "No targets available"
-        //     ]));
-        // }
-
-        let mut new_capturer = Capturer::build(scap::capturer::Options {
-            fps: settings.fps,
-            show_cursor: settings.show_cursor,
-            show_highlight: true,
-            target: None,
-            crop_area: None,
-            output_type: scap::frame::FrameType::BGR0,
-            output_resolution: scap::capturer::Resolution::Captured,
-            excluded_targets: None,
-        })
-        .map_err(|err| gst::error_msg!(gst::LibraryError::Init, ["{err}"]))?;
-
-        if settings.perform_internal_preroll {
-            gst::info!(CAT, imp = self, "Performing internal preroll");
-            new_capturer.start_capture();
-            let frame = new_capturer.get_next_frame().map_err(|err| {
-                gst::error_msg!(
-                    gst::LibraryError::Init,
-                    ["Failed to perform internal preroll: {err}"]
-                )
-            })?;
-            let frame_info = FrameInfo::new(&frame).unwrap();
-            let video_info = gst_video::VideoInfo::builder(
-                frame_info.gst_v_format,
-                frame_info.width,
-                frame_info.height,
-            )
-            .build()
-            .map_err(|err| {
-                gst::error_msg!(
-                    gst::LibraryError::Init,
-                    ["Failed to create video info: {err}"]
-                )
-            })?;
+    /// Stop capturing, returning once the capturer is stopped
+    fn stop(self) {
+        match self {
+            Capture::Idle(mut capturer) => capturer.stop_capture(),
+            Capture::Running(thread) => thread.stop(),
+        }
+    }
+}
 
-            // Deadlock prevention
-            drop(settings);
+/// Why building a capturer failed
+enum BuildError {
+    Scap(scap::capturer::CapturerBuildError),
+    /// scap panicked with this message
+    Panicked(String),
+}
 
-            self.obj()
-                .set_caps(
-                    &video_info
-                        .to_caps()
-                        .map_err(|err| gst::error_msg!(gst::LibraryError::Init, ["{err}"]))?,
-                )
-                .map_err(|err| gst::error_msg!(gst::LibraryError::Init, ["{err}"]))?;
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::Scap(err) => err.fmt(f),
+            BuildError::Panicked(message) => write!(f, "Failed to set up capturing: {message}"),
+        }
+    }
+}
 
-            let mut state = self.state.lock().unwrap();
-            state.base_time = frame_info.pts;
+/// Build a capturer with `options`.
+///
+/// scap panics where it can't set up capturing, e.g. on Linux without a
+/// session bus to reach the portal through. That is returned as an error
+/// instead, so the thread building the capturer can still report it.
+fn try_build_capturer(options: scap::capturer::Options) -> Result<Capturer, BuildError> {
+    match panic::catch_unwind(AssertUnwindSafe(|| Capturer::build(options))) {
+        Ok(res) => {
+            let capturer = res.map_err(BuildError::Scap)?;
+            CAPTURERS_BUILT.fetch_add(1, Ordering::SeqCst);
+            Ok(capturer)
         }
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown error");
+            Err(BuildError::Panicked(message.to_string()))
+        }
+    }
+}
 
-        *capturer = Some(new_capturer);
+/// Frames `create()` produced for `try_pull_frame()`
+#[derive(Default)]
+struct FrameRequests {
+    /// The last frame produced while requests were waiting
+    sample: Option<gst::Sample>,
+    /// Counts the frames produced for requests, so a waiting one can tell a
+    /// frame was produced since it started waiting
+    serial: u64,
+    /// Playing, so frames are being produced
+    streaming: bool,
+}
 
-        gst::debug!(CAT, imp = self, "Capturer created");
+pub struct ScapSrc {
+    settings: Mutex<Settings>,
+    capture: Mutex<Option<Capture>>,
+    frames: Arc<FrameQueue>,
+    frame_requests: Mutex<FrameRequests>,
+    frame_produced: Condvar,
+    /// `try_pull_frame()` calls waiting for a frame. `create()` only keeps a
+    /// frame for them while there are any, as holding on to the buffers would
+    /// make in-place elements downstream copy them.
+    waiting_frame_requests: AtomicUsize,
+    /// Thread building the capturer for the last `start()`
+    start_thread: Mutex<Option<thread::JoinHandle<()>>>,
+    state: Mutex<State>,
+    /// Signalled with the state locked when `State::generation` changes
+    generation_changed: Condvar,
+}
 
-        Ok(())
+impl Default for ScapSrc {
+    fn default() -> Self {
+        Self {
+            settings: Mutex::new(Default::default()),
+            capture: Mutex::new(None),
+            frames: Default::default(),
+            frame_requests: Default::default(),
+            frame_produced: Condvar::new(),
+            waiting_frame_requests: AtomicUsize::new(0),
+            start_thread: Mutex::new(None),
+            state: Mutex::new(Default::default()),
+            generation_changed: Condvar::new(),
+        }
     }
+}
 
-    fn stop(&self) -> Result<(), gst::ErrorMessage> {
-        match self.capturer.lock().unwrap().take() {
-            Some(mut c) => c.stop_capture(),
-            None => {
-                return Err(gst::error_msg!(
-                    gst::LibraryError::Shutdown,
-                    ["Missing capturer"]
-                ));
+impl ScapSrc {
+    fn build_capturer(&self, settings: &Settings) -> Result<Capturer, gst::ErrorMessage> {
+        try_build_capturer(settings.capturer_options()?).map_err(|err| self.build_error(err))
+    }
+
+    /// Report that building the capturer failed for good.
+    fn build_error(&self, err: BuildError) -> gst::ErrorMessage {
+        let code = match &err {
+            BuildError::Scap(scap::capturer::CapturerBuildError::PermissionNotGranted) => {
+                ScapErrorCode::Permission
+            }
+            BuildError::Scap(scap::capturer::CapturerBuildError::NotSupported)
+            | BuildError::Panicked(_) => ScapErrorCode::Fatal,
+        };
+        let message = err.to_string();
+        self.post_scap_error(code, message.clone());
+
+        match err {
+            BuildError::Scap(_) => gst::error_msg!(gst::LibraryError::Init, ["{message}"]),
+            BuildError::Panicked(_) => {
+                gst::error_msg!(gst::ResourceError::OpenRead, ["{message}"])
             }
         }
+    }
 
-        Ok(())
+    /// Post a `scapsrc-error` message for applications to match on.
+    fn post_scap_error(&self, code: ScapErrorCode, message: String) {
+        let obj = self.obj();
+        let _ = obj.post_message(ScapError { code, message }.to_message(&obj));
     }
 
-    fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
-        let info = gst_video::VideoInfo::from_caps(caps).map_err(|_| {
-            gst::loggable_error!(CAT, "Failed to build `VideoInfo` from caps {}", caps)
+    /// Wait up to `timeout` for the next frame from the capture thread,
+    /// giving up once unlocked or the duration was reached
+    fn next_frame(&self, timeout: Option<Duration>) -> Result<Option<QueuedFrame>, gst::FlowError> {
+        match self.frames.pop(timeout) {
+            Ok(frame) => Ok(frame),
+            Err(gst::FlowError::Eos) => {
+                gst::debug!(CAT, imp = self, "Reached configured duration");
+                Err(gst::FlowError::Eos)
+            }
+            Err(err) => {
+                gst::debug!(CAT, imp = self, "Unlocked while waiting for a frame");
+                Err(err)
+            }
+        }
+    }
+
+    /// Replace a running capturer with one built from the current settings.
+    ///
+    /// Frames are not delivered while the new capturer is starting up, so this
+    /// causes a brief gap in the output. Timestamps stay continuous as the base
+    /// time is kept.
+    ///
+    /// The old capturer is stopped before the new one is built, as on Linux
+    /// scap keeps one capture state for the whole process. Stopping any
+    /// capturer there ends all capturing of the process, including that of a
+    /// capturer built before. Building one also opens the screen cast portal
+    /// again, which may ask the user what to share again, so capturers are
+    /// only rebuilt where there's no other way.
+    fn restart_capturer(&self) -> Result<(), gst::ErrorMessage> {
+        let mut capture = self.capture.lock().unwrap();
+        let Some(old_capture) = capture.take() else {
+            // Not started yet, the new settings are picked up in `start()`
+            return Ok(());
+        };
+        old_capture.stop();
+
+        let settings = self.settings.lock().unwrap().clone();
+        let new_capturer = self.build_capturer(&settings).inspect_err(|_| {
+            // `create()` would wait for frames from it forever otherwise
+            self.frames.push(Err(mpsc::RecvError));
         })?;
+        let mut new_capture = Capture::Idle(new_capturer);
+        if self.obj().current_state() == gst::State::Playing {
+            new_capture = new_capture.start(&self.frames);
+        }
 
-        gst::debug!(CAT, imp = self, "Configuring for caps {}", caps);
+        *capture = Some(new_capture);
 
-        let (new_width, new_height) = (info.width(), info.height());
+        gst::debug!(CAT, imp = self, "Capturer restarted");
 
-        self.obj().set_blocksize(4 * new_width * new_height);
+        Ok(())
+    }
 
-        let mut state = self.state.lock().unwrap();
+    /// Stop the capturer and replace it with a running one built from the
+    /// current settings.
+    ///
+    /// The old capturer is stopped first, as for `restart_capturer()`.
+    fn rebuild_capturer(&self) -> Result<(), gst::ErrorMessage> {
+        let mut capture = self.capture.lock().unwrap();
+        if let Some(old_capture) = capture.take() {
+            old_capture.stop();
+        }
 
-        state.info = Some(info);
-        state.width = new_width as i32;
-        state.height = new_height as i32;
+        let settings = self.settings.lock().unwrap().clone();
+        let new_capturer = self.build_capturer(&settings)?;
+        *capture = Some(Capture::Idle(new_capturer).start(&self.frames));
+
+        gst::debug!(CAT, imp = self, "Capturer rebuilt");
 
         Ok(())
     }
 
-    fn query(&self, query: &mut gst::QueryRef) -> bool {
-        use gst::QueryViewMut;
-        let settings = self.settings.lock().unwrap();
-        match query.view_mut() {
-            QueryViewMut::Caps(q) if settings.perform_internal_preroll => {
-                gst::info!(CAT, imp = self, "Returning caps");
-                let state = self.state.lock().unwrap();
-                if let Some(info) = &state.info.as_ref() {
-                    q.set_result(Some(&info.to_caps().unwrap()));
-                    true
-                } else {
-                    false
-                }
-            }
-            _ => {
-                drop(settings);
-                BaseSrcImplExt::parent_query(self, query)
-            }
+    /// The next frame produced while streaming, waiting up to `timeout` for
+    /// it.
+    ///
+    /// The frame is taken from the running capturer instead of one built for
+    /// it, see `restart_capturer()`.
+    pub(super) fn try_pull_frame(&self, timeout: Option<gst::ClockTime>) -> Option<gst::Sample> {
+        let requests = self.frame_requests.lock().unwrap();
+        if !requests.streaming {
+            gst::error!(CAT, imp = self, "Can't pull a frame while not playing");
+            return None;
         }
-    }
-}
 
-impl PushSrcImpl for ScapSrc {
-    fn create(&self, _: Option<&mut gst::BufferRef>) -> Result<CreateSuccess, gst::FlowError> {
-        let Some(ref cap) = *self.capturer.lock().unwrap() else {
-            return Err(gst::FlowError::NotNegotiated);
+        let serial = requests.serial;
+        self.waiting_frame_requests.fetch_add(1, Ordering::SeqCst);
+        let waiting =
+            |requests: &mut FrameRequests| requests.streaming && requests.serial == serial;
+        let mut requests = match timeout {
+            Some(timeout) => {
+                let timeout = Duration::from_nanos(timeout.nseconds());
+                self.frame_produced
+                    .wait_timeout_while(requests, timeout, waiting)
+                    .unwrap()
+                    .0
+            }
+            None => self.frame_produced.wait_while(requests, waiting).unwrap(),
         };
 
-        let frame = cap.get_next_frame().map_err(|err| {
-            gst::element_error!(
-                self.obj(),
-                gst::ResourceError::Read,
-                ("Failed to get next frame: {err}")
-            );
-            gst::FlowError::Error
-        })?;
+        let sample = (requests.serial != serial)
+            .then(|| requests.sample.clone())
+            .flatten();
+        // The last waiting request lets go of the frame
+        if self.waiting_frame_requests.fetch_sub(1, Ordering::SeqCst) == 1 {
+            requests.sample = None;
+        }
 
-        let Some(frame_info) = FrameInfo::new(&frame) else {
-            gst::element_error!(
-                self.obj(),
-                gst::ResourceError::Failed,
-                ("Unsupported frame format received")
-            );
-            return Err(gst::FlowError::Error);
-        };
+        if sample.is_none() {
+            gst::debug!(CAT, imp = self, "No frame was produced");
+        }
+        sample
+    }
 
-        self.ensure_correct_format(&frame_info)?;
+    /// Start or stop accepting `try_pull_frame()` calls, waking up the waiting
+    /// ones
+    fn set_streaming(&self, streaming: bool) {
+        let mut requests = self.frame_requests.lock().unwrap();
+        requests.sample = None;
+        requests.streaming = streaming;
+        drop(requests);
+        self.frame_produced.notify_all();
+    }
 
-        let mut buffer = match frame {
-            scap::frame::Frame::RGB(f) => gst::Buffer::from_slice(f.data),
-            scap::frame::Frame::RGBx(f) => gst::Buffer::from_slice(f.data),
-            scap::frame::Frame::XBGR(f) => gst::Buffer::from_slice(f.data),
-            scap::frame::Frame::BGRx(f) => gst::Buffer::from_slice(f.data),
-            scap::frame::Frame::BGR0(f) => gst::Buffer::from_slice(f.data),
-            scap::frame::Frame::BGRA(f) => gst::Buffer::from_slice(f.data),
-            _ => unreachable!(), // Yuv format should already have returned an error
+    /// Capture a single frame with a capturer built from `settings`.
+    fn pull_frame_with(
+        &self,
+        settings: &Settings,
+        timeout: Option<gst::ClockTime>,
+    ) -> Option<gst::Sample> {
+        let (mut capturer, fps) = match self.build_capturer(settings) {
+            Ok(capturer) => (capturer, settings.output_fps()),
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Failed to build capturer: {err:?}");
+                return None;
+            }
         };
 
-        let mut state = self.state.lock().unwrap();
-        if state.base_time == u64::default() {
-            state.base_time = frame_info.pts;
+        // `get_next_frame()` can't time out, so wait for it on another thread.
+        // That thread stops the capturer once a frame arrives after timing out
+        capturer.start_capture();
+        let frames = Arc::new(FrameQueue::default());
+        let capture_thread = CaptureThread::spawn(capturer, frames.clone());
+        let frame = frames
+            .pop(timeout.map(|timeout| Duration::from_nanos(timeout.nseconds())))
+            .ok()
+            .flatten()
+            .map(|queued| queued.frame);
+        match frame {
+            Some(_) => capture_thread.stop(),
+            None => capture_thread.abandon(),
         }
 
-        let pts = frame_info.pts - state.base_time;
+        let frame = match frame {
+            Some(Ok(frame)) => frame,
+            Some(Err(err)) => {
+                gst::warning!(CAT, imp = self, "Failed to get frame: {err}");
+                return None;
+            }
+            None => {
+                gst::debug!(CAT, imp = self, "No frame received");
+                return None;
+            }
+        };
 
-        let buf = buffer.get_mut().unwrap();
-        buf.set_pts(gst::ClockTime::from_nseconds(pts));
+        let Some(frame_info) = FrameInfo::new(&frame) else {
+            gst::warning!(CAT, imp = self, "Unsupported frame format received");
+            return None;
+        };
+
+        let info = match frame_info.video_info(fps) {
+            Ok(info) => info,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Failed to create video info: {err}");
+                return None;
+            }
+        };
+        let caps = match info.to_caps() {
+            Ok(caps) => caps,
+            Err(err) => {
+                gst::error!(CAT, imp = self, "Failed to create caps: {err}");
+                return None;
+            }
+        };
+
+        let mut buffer = frame_to_buffer(frame);
+        let buf = buffer.get_mut().unwrap();
+        buf.set_pts(gst::ClockTime::ZERO);
+        if let Err(err) = add_packed_video_meta(buf, &info) {
+            gst::error!(CAT, imp = self, "Failed to add video meta: {err}");
+            return None;
+        }
+
+        Some(gst::Sample::builder().buffer(&buffer).caps(&caps).build())
+    }
+
+    /// Capture the given area of the target from now on, restarting the
+    /// capturer if running.
+    fn set_crop_region(&self, x: u32, y: u32, width: u32, height: u32) {
+        {
+            let mut settings = self.settings.lock().unwrap();
+
+            gst::info!(
+                CAT,
+                imp = self,
+                "Crop region was changed from `{}x{}+{}+{}` to `{width}x{height}+{x}+{y}`",
+                settings.crop_width,
+                settings.crop_height,
+                settings.crop_x,
+                settings.crop_y,
+            );
+
+            settings.crop_x = x;
+            settings.crop_y = y;
+            settings.crop_width = width;
+            settings.crop_height = height;
+        }
+
+        // Cropping is not supported on Linux, so the capturer isn't restarted
+        // for nothing there (see `restart_capturer()`)
+        if !cfg!(target_os = "linux") {
+            if let Err(err) = self.restart_capturer() {
+                self.post_error_message(err);
+                return;
+            }
+        }
+
+        self.obj()
+            .emit_by_name::<()>("region-changed", &[&x, &y, &width, &height]);
+    }
+
+    /// Build the capturer for the `start()` of `generation`, and start
+    /// capturing if already playing.
+    ///
+    /// Returns `false` if stopped or started again meanwhile, in which case
+    /// the capturer is discarded and starting must not be completed.
+    fn start_capturer(
+        &self,
+        settings: &Settings,
+        generation: u64,
+    ) -> Result<bool, gst::ErrorMessage> {
+        let options = settings.capturer_options()?;
+
+        // Building fails at times while the portal session isn't ready yet
+        let mut retries = 0;
+        let mut new_capturer = loop {
+            match try_build_capturer(options.clone()) {
+                Ok(capturer) => break capturer,
+                Err(err) if retries < settings.start_retries => {
+                    let delay_ms = settings
+                        .start_retry_delay_ms
+                        .saturating_mul(1 << retries.min(16));
+                    retries += 1;
+                    self.post_scap_error(
+                        ScapErrorCode::Transient,
+                        format!("Failed to build capturer: {err}"),
+                    );
+                    gst::info!(
+                        CAT,
+                        imp = self,
+                        "Failed to build capturer: {err}. Retrying in {delay_ms} ms (attempt {retries}/{})",
+                        settings.start_retries,
+                    );
+
+                    // Woken up early when stopped or started again
+                    let state = self.state.lock().unwrap();
+                    let (state, _) = self
+                        .generation_changed
+                        .wait_timeout_while(
+                            state,
+                            Duration::from_millis(delay_ms.into()),
+                            |state| state.generation == generation,
+                        )
+                        .unwrap();
+                    if state.generation != generation {
+                        gst::debug!(CAT, imp = self, "Stopped while retrying to start");
+                        return Ok(false);
+                    }
+                }
+                Err(err) => return Err(self.build_error(err)),
+            }
+        };
+
+        // Discards the capturer of a stale start before it touches any state
+        let discard = |mut capturer: Capturer| {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Stopped while starting, discarding capturer"
+            );
+            capturer.stop_capture();
+            Ok(false)
+        };
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.generation != generation {
+                drop(state);
+                return discard(new_capturer);
+            }
+            state.capture_backend = Some(capture_backend());
+            state.target_kind = Some(if cfg!(target_os = "linux") {
+                // Picked by the user through the portal
+                TargetKind::Unknown
+            } else if settings.crop_area().is_some() {
+                TargetKind::Region
+            } else if settings.target.starts_with("window:") {
+                TargetKind::Window
+            } else {
+                // scap captures the main display without a target
+                TargetKind::Display
+            });
+            // Not known on all platforms, in which case it's reported as 0x0
+            state.target_resolution = match new_capturer.get_output_frame_size() {
+                [0, 0] => None,
+                [width, height] => Some((width, height)),
+            };
+        }
+        gst::info!(CAT, imp = self, "Capturing with {}", capture_backend());
+
+        if settings.perform_internal_preroll {
+            if let Err(err) = self.perform_internal_preroll(&mut new_capturer, settings, generation)
+            {
+                new_capturer.stop_capture();
+                return Err(err);
+            }
+        }
+
+        let mut capture = self.capture.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation {
+            drop(state);
+            return discard(new_capturer);
+        }
+        state.starting = false;
+
+        // The internal preroll started capturing already
+        let new_capture = if settings.perform_internal_preroll {
+            Capture::Running(CaptureThread::spawn(new_capturer, self.frames.clone()))
+        } else if state.playing {
+            gst::info!(CAT, imp = self, "Capturing engine was started");
+            Capture::Idle(new_capturer).start(&self.frames)
+        } else {
+            Capture::Idle(new_capturer)
+        };
+        drop(state);
+
+        *capture = Some(new_capture);
+
+        gst::debug!(CAT, imp = self, "Capturer created");
+
+        Ok(true)
+    }
+
+    /// Encode the next frame produced by `create()` as `format`, `png` or
+    /// `jpeg`.
+    fn snapshot(&self, format: &str, timeout: Option<gst::ClockTime>) -> Option<glib::Bytes> {
+        let caps = match format {
+            "png" => gst::Caps::new_empty_simple("image/png"),
+            "jpeg" => gst::Caps::new_empty_simple("image/jpeg"),
+            _ => {
+                gst::error!(CAT, imp = self, "Unsupported snapshot format `{format}`");
+                return None;
+            }
+        };
+
+        let sample = self.try_pull_frame(timeout)?;
+        self.encode_sample(&sample, &caps, timeout)
+    }
+
+    /// Capture a single small frame of the target with `id` and encode it as
+    /// PNG, for previews in target pickers.
+    fn target_thumbnail(&self, id: &str, timeout: Option<gst::ClockTime>) -> Option<glib::Bytes> {
+        // Always the case on Linux, where targets are picked through the portal
+        if sorted_targets().is_empty() {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Can't capture target `{id}`, no targets are listed"
+            );
+            return None;
+        }
+
+        // A second capturer could interfere with the running one
+        let capture = self.capture.lock().unwrap();
+        if capture.is_some() || self.state.lock().unwrap().starting {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Can't capture target `{id}` while capturing"
+            );
+            return None;
+        }
+
+        // The whole target at the smallest size scap captures at
+        let mut settings = self.settings.lock().unwrap().clone();
+        settings.target = id.to_string();
+        settings.crop_width = 0;
+        settings.crop_height = 0;
+        settings.output_width = THUMBNAIL_WIDTH;
+        settings.output_height = 0;
+        settings.show_cursor = false;
+
+        let sample = self.pull_frame_with(&settings, timeout);
+        drop(capture);
+
+        let sample = sample?;
+        let info = gst_video::VideoInfo::from_caps(sample.caps()?).ok()?;
+        let height = (THUMBNAIL_WIDTH as u64 * info.height() as u64 / info.width().max(1) as u64)
+            .max(1) as i32;
+
+        let caps = gst::Caps::builder("image/png")
+            .field("width", THUMBNAIL_WIDTH as i32)
+            .field("height", height)
+            .build();
+        self.encode_sample(&sample, &caps, timeout)
+    }
+
+    /// Convert `sample` to the image format of `caps`.
+    fn encode_sample(
+        &self,
+        sample: &gst::Sample,
+        caps: &gst::Caps,
+        timeout: Option<gst::ClockTime>,
+    ) -> Option<glib::Bytes> {
+        let encoded =
+            match gst_video::convert_sample(sample, caps, timeout.unwrap_or(gst::ClockTime::MAX)) {
+                Ok(encoded) => encoded,
+                Err(err) => {
+                    gst::error!(CAT, imp = self, "Failed to encode frame: {err}");
+                    return None;
+                }
+            };
+
+        let buffer = encoded.buffer_owned()?;
+        match buffer.into_mapped_buffer_readable() {
+            Ok(map) => Some(glib::Bytes::from_owned(map)),
+            Err(_) => {
+                gst::error!(CAT, imp = self, "Failed to map encoded frame");
+                None
+            }
+        }
+    }
+
+    /// Lower the rate frames are delivered at while processing them takes up
+    /// most of the frame interval, and raise it again once there's headroom.
+    ///
+    /// Only a rate below the output fps is kept, so delivery isn't throttled
+    /// until processing fell behind.
+    fn adapt_fps(&self, state: &mut State, processing_time: Duration, settings: &FrameSettings) {
+        let processing_time = processing_time.as_nanos() as u64;
+        let avg = match state.avg_processing_time {
+            Some(avg) => (avg * 7 + processing_time) / 8,
+            None => processing_time,
+        };
+        state.avg_processing_time = Some(avg);
+
+        let max_fps = settings.fps;
+        let fps = state.adaptive_fps.unwrap_or(max_fps);
+        let interval = gst::ClockTime::SECOND.nseconds() / fps as u64;
+        let new_fps = if avg > interval * 8 / 10 {
+            fps.saturating_sub(1).max(settings.min_fps.min(max_fps))
+        } else if avg < interval / 2 {
+            (fps + 1).min(max_fps)
+        } else {
+            fps
+        };
+
+        if new_fps != fps {
+            gst::debug!(CAT, imp = self, "Adapting fps from {fps} to {new_fps}");
+        }
+        state.adaptive_fps = (new_fps < max_fps).then_some(new_fps);
+    }
+
+    /// Tags describing how the stream was captured with `backend`
+    fn capture_tags(backend: Option<&str>) -> gst::TagList {
+        let mut tags = gst::TagList::new();
+        {
+            let tags = tags.get_mut().unwrap();
+            let encoder = concat!("scapsrc ", env!("CARGO_PKG_VERSION"));
+            tags.add::<gst::tags::Encoder>(&encoder, gst::TagMergeMode::Replace);
+            if let Some(backend) = backend {
+                let comment = format!("Screen captured with scap using {backend}");
+                tags.add::<gst::tags::Comment>(&comment.as_str(), gst::TagMergeMode::Replace);
+            }
+        }
+
+        tags
+    }
+
+    /// Post a QoS message for a frame at `running_time` that was dropped.
+    ///
+    /// Must be called without holding the state lock, as bus handlers may
+    /// query properties.
+    fn post_qos(&self, running_time: u64, jitter: i64, processed: u64, dropped: u64) {
+        let time = gst::ClockTime::from_nseconds(running_time);
+        let _ = self.obj().post_message(
+            gst::message::Qos::builder(true, time, time, time, gst::ClockTime::NONE)
+                .values(jitter, 1.0, 1_000_000)
+                .stats(
+                    gst::format::Buffers::from_u64(processed),
+                    gst::format::Buffers::from_u64(dropped),
+                )
+                .src(&*self.obj())
+                .build(),
+        );
+    }
+
+    /// Pull one frame from `capturer` for the `start()` of `generation` and
+    /// configure the caps from it.
+    ///
+    /// The caller is responsible for stopping the capturer if this fails.
+    fn perform_internal_preroll(
+        &self,
+        capturer: &mut Capturer,
+        settings: &Settings,
+        generation: u64,
+    ) -> Result<(), gst::ErrorMessage> {
+        gst::info!(CAT, imp = self, "Performing internal preroll");
+
+        capturer.start_capture();
+        let frame = capturer.get_next_frame().map_err(|err| {
+            gst::error_msg!(
+                gst::LibraryError::Init,
+                ["Failed to perform internal preroll: {err}"]
+            )
+        })?;
+
+        if self.frames.is_flushing() || self.state.lock().unwrap().generation != generation {
+            return Err(gst::error_msg!(
+                gst::LibraryError::Init,
+                ["Internal preroll was cancelled"]
+            ));
+        }
+
+        let frame_info = FrameInfo::new(&frame).ok_or_else(|| {
+            gst::error_msg!(
+                gst::StreamError::Format,
+                ["Unsupported frame format received during internal preroll"]
+            )
+        })?;
+
+        let video_info = settings
+            .frame_settings()
+            .output_video_info(&frame_info)
+            .map_err(|err| {
+                gst::error_msg!(
+                    gst::LibraryError::Init,
+                    ["Failed to create video info: {err}"]
+                )
+            })?;
+
+        let caps = video_info.to_caps().map_err(|err| {
+            gst::error_msg!(gst::LibraryError::Init, ["Failed to create caps: {err}"])
+        })?;
+
+        self.obj().set_caps(&caps).map_err(|err| {
+            gst::error_msg!(gst::LibraryError::Init, ["Failed to set caps: {err}"])
+        })?;
+
+        let mut state = self.state.lock().unwrap();
+        state.requested_info = Some(video_info);
+        state.captured_frames += 1;
+        drop(state);
+
+        // Delivered as the first buffer, which latches the base time to it in
+        // `create()`
+        self.frames.push_preroll(frame);
+
+        Ok(())
+    }
+
+    /// Renegotiate if the output for frames of `frame_info` doesn't match the
+    /// current caps.
+    ///
+    /// With `auto-convert` a negotiated format different from the captured one
+    /// is kept, as frames get converted to it.
+    ///
+    /// Returns `false` if downstream didn't accept caps for the frame, e.g.
+    /// while a window is being resized. The frame then has to be scaled to the
+    /// caps still in place, and the pad is marked for reconfigure so the new
+    /// caps are tried again with the next differing frame.
+    ///
+    /// This is the only way resolution changes are picked up, as scap doesn't
+    /// notify about display changes ahead of delivering differently sized
+    /// frames.
+    ///
+    /// `state` is handed back, and only let go of while renegotiating.
+    fn ensure_correct_format<'a>(
+        &'a self,
+        state: MutexGuard<'a, State>,
+        frame_info: &FrameInfo,
+        settings: &FrameSettings,
+    ) -> Result<(MutexGuard<'a, State>, bool), gst::FlowError> {
+        let new_video_info = settings.output_video_info(frame_info).map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to create video info: {err}");
+            gst::FlowError::Error
+        })?;
+
+        match state.caps_fit(&new_video_info, settings.auto_convert) {
+            None => return Err(gst::FlowError::NotNegotiated),
+            Some(true) => return Ok((state, true)),
+            Some(false) => {}
+        }
+        // Downstream queries the caps while checking the new ones
+        drop(state);
+
+        gst::debug!(CAT, imp = self, "Formats differ. Will try to renegotiate");
+
+        let mut new_caps = new_video_info.to_caps().map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to create caps: {err}");
+            gst::FlowError::Error
+        })?;
+
+        if settings.auto_convert {
+            new_caps = self.conversion_caps(new_caps, &new_video_info);
+        }
+
+        // Already reported by `caps()` while downstream checks the new caps
+        self.state.lock().unwrap().requested_info = Some(new_video_info);
+
+        // The state lock must not be held here as `set_caps()` takes it
+        if let Err(err) = self.obj().set_caps(&new_caps) {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Failed to set caps {new_caps}: {err}. Scaling to the current caps",
+            );
+
+            self.obj().src_pad().mark_reconfigure();
+
+            // Report the caps in place again, so renegotiating on reconfigure
+            // doesn't fail on the ones just refused
+            let mut state = self.state.lock().unwrap();
+            state.requested_info = state.info.clone();
+            return Ok((state, false));
+        }
+
+        Ok((self.state.lock().unwrap(), true))
+    }
+
+    /// Start capturing when going to playing, dropping the frames captured
+    /// while paused with `freeze-on-pause`.
+    ///
+    /// The capturer keeps running while frozen instead of being rebuilt, see
+    /// `restart_capturer()`.
+    fn start_playing(&self) -> Result<(), gst::StateChangeError> {
+        let mut capture = self.capture.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        state.playing = true;
+        let was_frozen = std::mem::take(&mut state.frozen);
+        // Counted from the first time playing, not from starting, which an
+        // application may do long before it starts recording
+        if let Some(delay) = state.pending_startup_delay.take() {
+            state.startup_deadline = Some(Instant::now() + delay);
+        }
+        match capture.take() {
+            Some(c) => {
+                if matches!(c, Capture::Idle(_)) {
+                    gst::info!(CAT, imp = self, "Capturing engine was started");
+                }
+                *capture = Some(c.start(&self.frames));
+            }
+            // Started once built
+            None if state.starting => {}
+            None => {
+                gst::error!(CAT, imp = self, "Capturer is missing");
+                return Err(gst::StateChangeError);
+            }
+        }
+        drop(state);
+        drop(capture);
+        self.set_streaming(true);
+
+        if was_frozen {
+            self.frames.clear();
+            gst::info!(CAT, imp = self, "Capture was unfrozen");
+        }
+
+        Ok(())
+    }
+
+    /// Pick caps downstream accepts for frames of `video_info`, preferring
+    /// `native_caps` so no conversion is needed.
+    fn conversion_caps(
+        &self,
+        native_caps: gst::Caps,
+        video_info: &gst_video::VideoInfo,
+    ) -> gst::Caps {
+        let peer_caps = self.obj().src_pad().peer_query_caps(None);
+        if peer_caps.can_intersect(&native_caps) {
+            return native_caps;
+        }
+
+        let any_format_caps = gst_video::VideoCapsBuilder::new()
+            .width(video_info.width() as i32)
+            .height(video_info.height() as i32)
+            .framerate(video_info.fps())
+            .build();
+        let mut caps =
+            peer_caps.intersect_with_mode(&any_format_caps, gst::CapsIntersectMode::First);
+        if caps.is_empty() {
+            // Nothing downstream can take, let setting the native caps fail
+            return native_caps;
+        }
+
+        caps.fixate();
+
+        gst::debug!(CAT, imp = self, "Converting frames to {caps}");
+
+        caps
+    }
+
+    /// Convert `buffer` holding a frame of `frame_info` to the negotiated
+    /// format and size if they differ.
+    fn convert_to_negotiated(
+        &self,
+        state: &mut State,
+        buffer: gst::Buffer,
+        frame_info: &FrameInfo,
+        fps: u32,
+        scaling_mode: ScalingMode,
+    ) -> Result<gst::Buffer, gst::FlowError> {
+        let Some(out_info) = state.info.clone() else {
+            return Err(gst::FlowError::NotNegotiated);
+        };
+
+        let in_info = frame_info.video_info(fps).map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to create video info: {err}");
+            gst::FlowError::Error
+        })?;
+
+        if (out_info.format(), out_info.width(), out_info.height())
+            == (in_info.format(), in_info.width(), in_info.height())
+        {
+            return Ok(buffer);
+        }
+
+        if !matches!(&state.converter, Some((i, o, _)) if *i == in_info && *o == out_info) {
+            gst::debug!(
+                CAT,
+                imp = self,
+                "Creating converter from {} to {}",
+                in_info.format(),
+                out_info.format(),
+            );
+
+            let config = scaling_config(scaling_mode, &in_info, &out_info);
+            let converter =
+                gst_video::VideoConverter::new(&in_info, &out_info, config).map_err(|err| {
+                    gst::error!(CAT, imp = self, "Failed to create converter: {err}");
+                    gst::FlowError::NotNegotiated
+                })?;
+
+            state.converter = Some((in_info.clone(), out_info.clone(), converter));
+        }
+
+        let (_, _, converter) = state.converter.as_ref().unwrap();
+
+        let in_frame =
+            gst_video::VideoFrame::from_buffer_readable(buffer, &in_info).map_err(|_| {
+                gst::error!(CAT, imp = self, "Failed to map captured frame");
+                gst::FlowError::Error
+            })?;
+
+        let out_buffer = gst::Buffer::with_size(out_info.size()).map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to allocate buffer: {err}");
+            gst::FlowError::Error
+        })?;
+
+        let mut out_frame = gst_video::VideoFrame::from_buffer_writable(out_buffer, &out_info)
+            .map_err(|_| {
+                gst::error!(CAT, imp = self, "Failed to map output frame");
+                gst::FlowError::Error
+            })?;
+
+        converter.frame(&in_frame, &mut out_frame);
+
+        Ok(out_frame.into_buffer())
+    }
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ScapSrc {
+    const NAME: &'static str = "ScapSrc";
+    type Type = super::ScapSrc;
+    type ParentType = gst_base::PushSrc;
+}
+
+impl ObjectImpl for ScapSrc {
+    fn properties() -> &'static [glib::ParamSpec] {
+        static PROPERTIES: LazyLock<Vec<glib::ParamSpec>> = LazyLock::new(|| {
+            vec![
+                glib::ParamSpecUInt::builder("fps")
+                    .nick("Frames per second")
+                    .blurb("Rate to capture screen at. Changing it while playing restarts the capturer, which causes a brief gap in the output and on Linux may have the portal ask what to share again")
+                    .minimum(1)
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_FPS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("show-cursor")
+                    .nick("Show cursor")
+                    .blurb("Whether to capture the cursor or not. Changing it while playing restarts the capturer, which causes a brief gap in the output and on Linux may have the portal ask what to share again")
+                    .default_value(DEFAULT_SHOW_CURSOR)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("perform-internal-preroll")
+                    .nick("Perform internal preroll")
+                    .blurb("Pull one frame from the capture source before format negotiation and deliver it as the first buffer")
+                    .default_value(DEFAULT_PERFORM_INTERNAL_PREROLL)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt64::builder("duration")
+                    .nick("Duration")
+                    .blurb("Maximum duration to capture in nanoseconds before sending EOS (-1 = unlimited)")
+                    .default_value(u64::MAX)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("output-format", DEFAULT_OUTPUT_FORMAT)
+                    .nick("Output format")
+                    .blurb("Pixel format to request from the capture source. Has no effect on Linux, where frames are delivered in the format PipeWire negotiated")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("channel-order", ChannelOrder::of(DEFAULT_OUTPUT_FORMAT))
+                    .nick("Channel order")
+                    .blurb("Channel order to request from the capture source. Shorthand for output-format, setting it selects the rgb or bgr0 format. Like output-format, it has no effect on Linux")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("startup-delay-ms")
+                    .nick("Startup delay")
+                    .blurb("Time in milliseconds to discard frames for once playing for the first time after starting. Resuming from pause doesn't apply it again")
+                    .default_value(DEFAULT_STARTUP_DELAY_MS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("emit-signals")
+                    .nick("Emit signals")
+                    .blurb("Emit on-frame signals")
+                    .default_value(DEFAULT_EMIT_SIGNALS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-fps")
+                    .nick("Maximum frames per second")
+                    .blurb("Drop frames delivered faster than this rate (0 = unlimited)")
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_MAX_FPS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("heartbeat-interval")
+                    .nick("Heartbeat interval")
+                    .blurb("Interval in seconds to post heartbeat messages on the bus at (0 = disabled)")
+                    .default_value(DEFAULT_HEARTBEAT_INTERVAL)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("auto-convert")
+                    .nick("Auto convert")
+                    .blurb("Convert frames to the format downstream requires if it can't take the captured one")
+                    .default_value(DEFAULT_AUTO_CONVERT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-retries")
+                    .nick("Maximum retries")
+                    .blurb("Number of times to restart the capturer after failing to get a frame before erroring out")
+                    .default_value(DEFAULT_MAX_RETRIES)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("output-width")
+                    .nick("Output width")
+                    .blurb("Width to scale captured frames to (0 = captured width, or the width keeping the aspect ratio if only output-height is set)")
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_OUTPUT_WIDTH)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("output-height")
+                    .nick("Output height")
+                    .blurb("Height to scale captured frames to (0 = captured height, or the height keeping the aspect ratio if only output-width is set)")
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_OUTPUT_HEIGHT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-width")
+                    .nick("Maximum width")
+                    .blurb("Scale frames down keeping the aspect ratio if they would be wider than this (0 = no limit)")
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_MAX_WIDTH)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("max-height")
+                    .nick("Maximum height")
+                    .blurb("Scale frames down keeping the aspect ratio if they would be higher than this (0 = no limit)")
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_MAX_HEIGHT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("paused")
+                    .nick("Paused")
+                    .blurb("Discard captured frames without stopping the pipeline. Timestamps continue from the last frame on resume, so the output has no gap")
+                    .default_value(DEFAULT_PAUSED)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-x")
+                    .nick("Crop X")
+                    .blurb("Horizontal offset of the area to capture from the left edge of the target. When capturing a window the area moves along with it")
+                    .default_value(DEFAULT_CROP_X)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-y")
+                    .nick("Crop Y")
+                    .blurb("Vertical offset of the area to capture from the top edge of the target. When capturing a window the area moves along with it")
+                    .default_value(DEFAULT_CROP_Y)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-width")
+                    .nick("Crop width")
+                    .blurb("Width of the area to capture (0 = capture the whole target)")
+                    .default_value(DEFAULT_CROP_WIDTH)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("crop-height")
+                    .nick("Crop height")
+                    .blurb("Height of the area to capture (0 = capture the whole target)")
+                    .default_value(DEFAULT_CROP_HEIGHT)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("scaling-mode", DEFAULT_SCALING_MODE)
+                    .nick("Scaling mode")
+                    .blurb("How to fit frames into output-width and output-height if the aspect ratio differs. Letterbox and crop are done in software and cost more CPU")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("freeze-on-pause")
+                    .nick("Freeze on pause")
+                    .blurb("Drop the frames captured while the pipeline is paused instead of delivering them once playing again. Timestamps continue from the last frame, so the output has no gap. Capturing keeps running, as restarting it would have the portal ask what to share again on Linux")
+                    .default_value(DEFAULT_FREEZE_ON_PAUSE)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("on-unsupported-format", DEFAULT_ON_UNSUPPORTED_FORMAT)
+                    .nick("On unsupported format")
+                    .blurb("What to do with frames scap delivers in a format that can't be output")
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("adaptive-fps")
+                    .nick("Adaptive fps")
+                    .blurb("Drop frames to lower the delivered rate towards min-fps while processing frames can't keep up, and raise it again once it can. The framerate in the caps and the buffer durations stay at fps")
+                    .default_value(DEFAULT_ADAPTIVE_FPS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("min-fps")
+                    .nick("Minimum frames per second")
+                    .blurb("Lowest rate adaptive-fps lowers delivery to, at most fps")
+                    .minimum(1)
+                    .maximum(i32::MAX as u32)
+                    .default_value(DEFAULT_MIN_FPS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecString::builder("target")
+                    .nick("Target")
+                    .blurb("What to capture: \"primary\" for the primary display, \"default\" for whatever scap picks, or the id of a target as \"display:<title>#<id>\" or \"window:<title>#<id>\", from get-targets. Without a target with that id, the first one with the same title is captured. On Linux the target is always picked through the portal")
+                    .default_value(Some(DEFAULT_TARGET))
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("fixed-output-size")
+                    .nick("Fixed output size")
+                    .blurb("Keep the output size of the first frame for the whole stream, scaling frames to it according to scaling-mode if the target's resolution changes instead of renegotiating")
+                    .default_value(DEFAULT_FIXED_OUTPUT_SIZE)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("even-dimensions")
+                    .nick("Even dimensions")
+                    .blurb("Cut the last column or row off frames with an odd width or height, as most encoders only accept even sizes")
+                    .default_value(DEFAULT_EVEN_DIMENSIONS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("smooth-timestamps")
+                    .nick("Smooth timestamps")
+                    .blurb("Snap timestamps to an even 1/fps grid instead of using the jittery capture times. Dropped frames leave gaps in the grid")
+                    .default_value(DEFAULT_SMOOTH_TIMESTAMPS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("deterministic-timing")
+                    .nick("Deterministic timing")
+                    .blurb("Timestamp frames only by their number at the output frame rate, ignoring when they were captured, and deliver them at exactly that rate. Timestamps are the same for every run, e.g. for recordings in automated tests")
+                    .default_value(DEFAULT_DETERMINISTIC_TIMING)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecBoolean::builder("detect-black-frames")
+                    .nick("Detect black frames")
+                    .blurb("Post a warning once black-frame-threshold all black frames were captured in a row, as some platforms deliver black frames instead of failing. Checks a few pixels of every frame")
+                    .default_value(DEFAULT_DETECT_BLACK_FRAMES)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("black-frame-threshold")
+                    .nick("Black frame threshold")
+                    .blurb("Number of all black frames in a row after which detect-black-frames posts a warning")
+                    .minimum(1)
+                    .default_value(DEFAULT_BLACK_FRAME_THRESHOLD)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecBoolean::builder("fallback-on-stall")
+                    .nick("Fallback on stall")
+                    .blurb("Deliver black frames while scap delivers none for stall-timeout-ms, so the output keeps flowing. They have the format and size of the last captured frame, and real frames are delivered again as soon as they arrive")
+                    .default_value(DEFAULT_FALLBACK_ON_STALL)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("stall-timeout-ms")
+                    .nick("Stall timeout")
+                    .blurb("Milliseconds without frames after which fallback-on-stall delivers a black frame, and between the black frames after it")
+                    .minimum(1)
+                    .default_value(DEFAULT_STALL_TIMEOUT_MS)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecInt64::builder("ts-offset")
+                    .nick("Timestamp offset")
+                    .blurb("Offset in nanoseconds added to the timestamp of every buffer, e.g. to line up with audio. Timestamps that would become negative are clamped to 0")
+                    .default_value(DEFAULT_TS_OFFSET)
+                    .mutable_playing()
+                    .build(),
+                glib::ParamSpecUInt::builder("start-retries")
+                    .nick("Start retries")
+                    .blurb("Number of times to retry building the capturer when starting fails, e.g. while the portal session isn't ready yet (0 = fail right away)")
+                    .default_value(DEFAULT_START_RETRIES)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecUInt::builder("start-retry-delay-ms")
+                    .nick("Start retry delay")
+                    .blurb("Milliseconds to wait before the first start retry, doubled for every further retry")
+                    .default_value(DEFAULT_START_RETRY_DELAY_MS)
+                    .mutable_ready()
+                    .build(),
+                glib::ParamSpecString::builder("capture-backend")
+                    .nick("Capture backend")
+                    .blurb("The backend scap uses for capturing, available once started")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecString::builder("target-resolution")
+                    .nick("Target resolution")
+                    .blurb("Resolution of the captured target as WxH, available once started if scap can determine it")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecDouble::builder("capture-fps-actual")
+                    .nick("Actual capture fps")
+                    .blurb("Rate frames were delivered at over the last second, reflecting max-fps")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecUInt64::builder("capture-latency")
+                    .nick("Capture latency")
+                    .blurb("Average time in nanoseconds from scap capturing a frame to delivering it, included in the reported latency (0 = not measured yet)")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecEnum::builder_with_default("target-kind", TargetKind::Unknown)
+                    .nick("Target kind")
+                    .blurb("Kind of target being captured, available once started")
+                    .read_only()
+                    .build(),
+                glib::ParamSpecBoxed::builder::<gst::Caps>("current-caps")
+                    .nick("Current caps")
+                    .blurb("The currently negotiated caps, if any")
+                    .read_only()
+                    .build(),
+                // glib::ParamSpecBoxed::builder::<Option<glib::Closure>>("select-target-cb")
+                //     .nick("Select target callback")
+                //     .blurb("Function that accepts a list of targets and returns the target that should be captured")
+                //     .mutable_ready()
+                //     .build(),
+            ]
+        });
+
+        &PROPERTIES
+    }
+
+    fn signals() -> &'static [glib::subclass::Signal] {
+        static SIGNALS: LazyLock<Vec<glib::subclass::Signal>> = LazyLock::new(|| {
+            vec![
+                // Emitted for every produced frame with its pts and the width and
+                // height of the buffer
+                glib::subclass::Signal::builder("on-frame")
+                    .param_types([u64::static_type(), u32::static_type(), u32::static_type()])
+                    .build(),
+                // Action signal to change the captured area while running, with
+                // x, y, width and height
+                glib::subclass::Signal::builder("set-crop-region")
+                    .param_types([
+                        u32::static_type(),
+                        u32::static_type(),
+                        u32::static_type(),
+                        u32::static_type(),
+                    ])
+                    .action()
+                    .class_handler(|args| {
+                        let element = args[0].get::<super::ScapSrc>().expect("signal arg");
+                        let x = args[1].get::<u32>().expect("signal arg");
+                        let y = args[2].get::<u32>().expect("signal arg");
+                        let width = args[3].get::<u32>().expect("signal arg");
+                        let height = args[4].get::<u32>().expect("signal arg");
+
+                        element.imp().set_crop_region(x, y, width, height);
+
+                        None
+                    })
+                    .build(),
+                // Action signal encoding the next produced frame as the given
+                // format (`png` or `jpeg`). Only works while playing, waiting
+                // for the frame up to the timeout in nanoseconds (-1 = wait
+                // forever).
+                glib::subclass::Signal::builder("snapshot")
+                    .param_types([String::static_type(), u64::static_type()])
+                    .return_type::<Option<glib::Bytes>>()
+                    .action()
+                    .class_handler(|args| {
+                        let element = args[0].get::<super::ScapSrc>().expect("signal arg");
+                        let format = args[1].get::<String>().expect("signal arg");
+                        let timeout = args[2].get::<u64>().expect("signal arg");
+                        let timeout =
+                            (timeout != u64::MAX).then(|| gst::ClockTime::from_nseconds(timeout));
+
+                        Some(element.imp().snapshot(&format, timeout).to_value())
+                    })
+                    .build(),
+                // Action signal listing the capturable targets as an array of
+                // structures with id, name and kind. Always empty on Linux,
+                // where the target is picked through the portal
+                glib::subclass::Signal::builder("get-targets")
+                    .return_type::<glib::ValueArray>()
+                    .action()
+                    .class_handler(|_| {
+                        let targets = sorted_targets()
+                            .iter()
+                            .map(target_structure)
+                            .collect::<Vec<_>>();
+
+                        Some(glib::ValueArray::new(targets).to_value())
+                    })
+                    .build(),
+                // Action signal capturing a single frame of the target with
+                // the given id from get-targets as a small PNG, for previews in
+                // target pickers. Gives up after the timeout in nanoseconds
+                // (-1 = wait forever). Fails while capturing and on Linux,
+                // where no targets are listed
+                glib::subclass::Signal::builder("get-target-thumbnail")
+                    .param_types([String::static_type(), u64::static_type()])
+                    .return_type::<Option<glib::Bytes>>()
+                    .action()
+                    .class_handler(|args| {
+                        let element = args[0].get::<super::ScapSrc>().expect("signal arg");
+                        let id = args[1].get::<String>().expect("signal arg");
+                        let timeout = args[2].get::<u64>().expect("signal arg");
+                        let timeout =
+                            (timeout != u64::MAX).then(|| gst::ClockTime::from_nseconds(timeout));
+
+                        Some(element.imp().target_thumbnail(&id, timeout).to_value())
+                    })
+                    .build(),
+                // Emitted with x, y, width and height after the crop region was
+                // changed with set-crop-region
+                glib::subclass::Signal::builder("region-changed")
+                    .param_types([
+                        u32::static_type(),
+                        u32::static_type(),
+                        u32::static_type(),
+                        u32::static_type(),
+                    ])
+                    .build(),
+            ]
+        });
+
+        &SIGNALS
+    }
+
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.set_live(true);
+        obj.set_format(gst::Format::Time);
+        obj.set_async(true);
+    }
+
+    fn set_property(&self, _id: usize, value: &glib::Value, pspec: &glib::ParamSpec) {
+        match pspec.name() {
+            "fps" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_fps = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "fps was changed from `{}` to `{}`",
+                    settings.fps,
+                    new_fps
+                );
+
+                settings.fps = new_fps;
+                drop(settings);
+
+                if let Err(err) = self.restart_capturer() {
+                    self.post_error_message(err);
+                }
+            }
+            "show-cursor" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_show_cursor = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "show-cursor was changed from `{}` to `{}`",
+                    settings.show_cursor,
+                    new_show_cursor
+                );
+
+                settings.show_cursor = new_show_cursor;
+                drop(settings);
+
+                if let Err(err) = self.restart_capturer() {
+                    self.post_error_message(err);
+                }
+            }
+            "perform-internal-preroll" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_perf_internal_preroll = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "perform-internal-preroll was changed from `{}` to `{}`",
+                    settings.perform_internal_preroll,
+                    new_perf_internal_preroll,
+                );
+
+                settings.perform_internal_preroll = new_perf_internal_preroll;
+            }
+            "duration" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_duration = value.get::<u64>().expect("type checked upstream");
+                let new_duration =
+                    (new_duration != u64::MAX).then(|| gst::ClockTime::from_nseconds(new_duration));
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "duration was changed from `{}` to `{}`",
+                    settings.duration.display(),
+                    new_duration.display(),
+                );
+
+                settings.duration = new_duration;
+            }
+            "output-format" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_output_format = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "output-format was changed from `{:?}` to `{:?}`",
+                    settings.output_format,
+                    new_output_format,
+                );
+
+                settings.output_format = new_output_format;
+            }
+            "channel-order" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_channel_order = value.get::<ChannelOrder>().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "channel-order was changed from `{:?}` to `{:?}`",
+                    ChannelOrder::of(settings.output_format),
+                    new_channel_order,
+                );
+
+                settings.output_format = new_channel_order.output_format();
+                drop(settings);
+
+                self.obj().notify("output-format");
+            }
+            "startup-delay-ms" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_startup_delay_ms = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "startup-delay-ms was changed from `{}` to `{}`",
+                    settings.startup_delay_ms,
+                    new_startup_delay_ms,
+                );
+
+                settings.startup_delay_ms = new_startup_delay_ms;
+            }
+            "emit-signals" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_emit_signals = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "emit-signals was changed from `{}` to `{}`",
+                    settings.emit_signals,
+                    new_emit_signals,
+                );
+
+                settings.emit_signals = new_emit_signals;
+            }
+            "max-fps" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_max_fps = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "max-fps was changed from `{}` to `{}`",
+                    settings.max_fps,
+                    new_max_fps,
+                );
+
+                settings.max_fps = new_max_fps;
+            }
+            "heartbeat-interval" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_heartbeat_interval = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "heartbeat-interval was changed from `{}` to `{}`",
+                    settings.heartbeat_interval,
+                    new_heartbeat_interval,
+                );
+
+                settings.heartbeat_interval = new_heartbeat_interval;
+            }
+            "auto-convert" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_auto_convert = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "auto-convert was changed from `{}` to `{}`",
+                    settings.auto_convert,
+                    new_auto_convert,
+                );
+
+                settings.auto_convert = new_auto_convert;
+            }
+            "max-retries" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_max_retries = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "max-retries was changed from `{}` to `{}`",
+                    settings.max_retries,
+                    new_max_retries,
+                );
+
+                settings.max_retries = new_max_retries;
+            }
+            "output-width" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_output_width = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "output-width was changed from `{}` to `{}`",
+                    settings.output_width,
+                    new_output_width,
+                );
+
+                settings.output_width = new_output_width;
+            }
+            "output-height" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_output_height = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "output-height was changed from `{}` to `{}`",
+                    settings.output_height,
+                    new_output_height,
+                );
+
+                settings.output_height = new_output_height;
+            }
+            "max-width" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_max_width = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "max-width was changed from `{}` to `{}`",
+                    settings.max_width,
+                    new_max_width,
+                );
+
+                settings.max_width = new_max_width;
+            }
+            "max-height" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_max_height = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "max-height was changed from `{}` to `{}`",
+                    settings.max_height,
+                    new_max_height,
+                );
+
+                settings.max_height = new_max_height;
+            }
+            "paused" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_paused = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "paused was changed from `{}` to `{}`",
+                    settings.paused,
+                    new_paused,
+                );
+
+                settings.paused = new_paused;
+            }
+            "crop-x" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_crop_x = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "crop-x was changed from `{}` to `{}`",
+                    settings.crop_x,
+                    new_crop_x,
+                );
+
+                settings.crop_x = new_crop_x;
+            }
+            "crop-y" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_crop_y = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "crop-y was changed from `{}` to `{}`",
+                    settings.crop_y,
+                    new_crop_y,
+                );
+
+                settings.crop_y = new_crop_y;
+            }
+            "crop-width" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_crop_width = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "crop-width was changed from `{}` to `{}`",
+                    settings.crop_width,
+                    new_crop_width,
+                );
+
+                settings.crop_width = new_crop_width;
+            }
+            "crop-height" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_crop_height = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "crop-height was changed from `{}` to `{}`",
+                    settings.crop_height,
+                    new_crop_height,
+                );
+
+                settings.crop_height = new_crop_height;
+            }
+            "scaling-mode" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_scaling_mode = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "scaling-mode was changed from `{:?}` to `{:?}`",
+                    settings.scaling_mode,
+                    new_scaling_mode,
+                );
+
+                settings.scaling_mode = new_scaling_mode;
+            }
+            "freeze-on-pause" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_freeze_on_pause = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "freeze-on-pause was changed from `{}` to `{}`",
+                    settings.freeze_on_pause,
+                    new_freeze_on_pause,
+                );
+
+                settings.freeze_on_pause = new_freeze_on_pause;
+            }
+            "on-unsupported-format" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_on_unsupported_format = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "on-unsupported-format was changed from `{:?}` to `{:?}`",
+                    settings.on_unsupported_format,
+                    new_on_unsupported_format,
+                );
+
+                settings.on_unsupported_format = new_on_unsupported_format;
+            }
+            "adaptive-fps" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_adaptive_fps = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "adaptive-fps was changed from `{}` to `{}`",
+                    settings.adaptive_fps,
+                    new_adaptive_fps,
+                );
+
+                settings.adaptive_fps = new_adaptive_fps;
+            }
+            "min-fps" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_min_fps = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "min-fps was changed from `{}` to `{}`",
+                    settings.min_fps,
+                    new_min_fps,
+                );
+
+                settings.min_fps = new_min_fps;
+            }
+            "target" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_target = value
+                    .get::<Option<String>>()
+                    .expect("type checked upstream")
+                    .unwrap_or_else(|| DEFAULT_TARGET.to_string());
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "target was changed from `{}` to `{}`",
+                    settings.target,
+                    new_target,
+                );
+
+                settings.target = new_target;
+            }
+            "fixed-output-size" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_fixed_output_size = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "fixed-output-size was changed from `{}` to `{}`",
+                    settings.fixed_output_size,
+                    new_fixed_output_size,
+                );
+
+                settings.fixed_output_size = new_fixed_output_size;
+            }
+            "smooth-timestamps" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_smooth_timestamps = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "smooth-timestamps was changed from `{}` to `{}`",
+                    settings.smooth_timestamps,
+                    new_smooth_timestamps,
+                );
+
+                settings.smooth_timestamps = new_smooth_timestamps;
+            }
+            "ts-offset" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_ts_offset = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "ts-offset was changed from `{}` to `{}`",
+                    settings.ts_offset,
+                    new_ts_offset,
+                );
+
+                settings.ts_offset = new_ts_offset;
+            }
+            "even-dimensions" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_even_dimensions = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "even-dimensions was changed from `{}` to `{}`",
+                    settings.even_dimensions,
+                    new_even_dimensions,
+                );
+
+                settings.even_dimensions = new_even_dimensions;
+            }
+            "detect-black-frames" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_detect_black_frames = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "detect-black-frames was changed from `{}` to `{}`",
+                    settings.detect_black_frames,
+                    new_detect_black_frames,
+                );
+
+                settings.detect_black_frames = new_detect_black_frames;
+            }
+            "black-frame-threshold" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_black_frame_threshold = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "black-frame-threshold was changed from `{}` to `{}`",
+                    settings.black_frame_threshold,
+                    new_black_frame_threshold,
+                );
+
+                settings.black_frame_threshold = new_black_frame_threshold;
+            }
+            "fallback-on-stall" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_fallback_on_stall = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "fallback-on-stall was changed from `{}` to `{}`",
+                    settings.fallback_on_stall,
+                    new_fallback_on_stall,
+                );
+
+                settings.fallback_on_stall = new_fallback_on_stall;
+            }
+            "stall-timeout-ms" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_stall_timeout_ms = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "stall-timeout-ms was changed from `{}` to `{}`",
+                    settings.stall_timeout_ms,
+                    new_stall_timeout_ms,
+                );
+
+                settings.stall_timeout_ms = new_stall_timeout_ms;
+            }
+            "deterministic-timing" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_deterministic_timing = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "deterministic-timing was changed from `{}` to `{}`",
+                    settings.deterministic_timing,
+                    new_deterministic_timing,
+                );
+
+                settings.deterministic_timing = new_deterministic_timing;
+            }
+            "start-retries" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_start_retries = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "start-retries was changed from `{}` to `{}`",
+                    settings.start_retries,
+                    new_start_retries,
+                );
+
+                settings.start_retries = new_start_retries;
+            }
+            "start-retry-delay-ms" => {
+                let mut settings = self.settings.lock().unwrap();
+                let new_start_retry_delay_ms = value.get().expect("type checked upstream");
+
+                gst::info!(
+                    CAT,
+                    imp = self,
+                    "start-retry-delay-ms was changed from `{}` to `{}`",
+                    settings.start_retry_delay_ms,
+                    new_start_retry_delay_ms,
+                );
+
+                settings.start_retry_delay_ms = new_start_retry_delay_ms;
+            }
+            // "select-target-cb" => {
+            //     let mut settings = self.settings.lock().unwrap();
+            //     let new_cb = value.get().expect("type checked upstream");
+
+            //     gst::info!(CAT, imp = self, "Changing select-target-cb");
+
+            //     settings.sel_target_cb = new_cb;
+            // }
+            _ => unimplemented!(),
+        }
+    }
+
+    fn property(&self, _id: usize, pspec: &glib::ParamSpec) -> glib::Value {
+        match pspec.name() {
+            "fps" => {
+                let settings = self.settings.lock().unwrap();
+                settings.fps.to_value()
+            }
+            "show-cursor" => {
+                let settings = self.settings.lock().unwrap();
+                settings.show_cursor.to_value()
+            }
+            "perform-internal-preroll" => {
+                let settings = self.settings.lock().unwrap();
+                settings.perform_internal_preroll.to_value()
+            }
+            "duration" => {
+                let settings = self.settings.lock().unwrap();
+                settings
+                    .duration
+                    .map_or(u64::MAX, gst::ClockTime::nseconds)
+                    .to_value()
+            }
+            "output-format" => {
+                let settings = self.settings.lock().unwrap();
+                settings.output_format.to_value()
+            }
+            "channel-order" => {
+                let settings = self.settings.lock().unwrap();
+                ChannelOrder::of(settings.output_format).to_value()
+            }
+            "startup-delay-ms" => {
+                let settings = self.settings.lock().unwrap();
+                settings.startup_delay_ms.to_value()
+            }
+            "emit-signals" => {
+                let settings = self.settings.lock().unwrap();
+                settings.emit_signals.to_value()
+            }
+            "max-fps" => {
+                let settings = self.settings.lock().unwrap();
+                settings.max_fps.to_value()
+            }
+            "heartbeat-interval" => {
+                let settings = self.settings.lock().unwrap();
+                settings.heartbeat_interval.to_value()
+            }
+            "auto-convert" => {
+                let settings = self.settings.lock().unwrap();
+                settings.auto_convert.to_value()
+            }
+            "max-retries" => {
+                let settings = self.settings.lock().unwrap();
+                settings.max_retries.to_value()
+            }
+            "output-width" => {
+                let settings = self.settings.lock().unwrap();
+                settings.output_width.to_value()
+            }
+            "output-height" => {
+                let settings = self.settings.lock().unwrap();
+                settings.output_height.to_value()
+            }
+            "max-width" => {
+                let settings = self.settings.lock().unwrap();
+                settings.max_width.to_value()
+            }
+            "max-height" => {
+                let settings = self.settings.lock().unwrap();
+                settings.max_height.to_value()
+            }
+            "paused" => {
+                let settings = self.settings.lock().unwrap();
+                settings.paused.to_value()
+            }
+            "crop-x" => {
+                let settings = self.settings.lock().unwrap();
+                settings.crop_x.to_value()
+            }
+            "crop-y" => {
+                let settings = self.settings.lock().unwrap();
+                settings.crop_y.to_value()
+            }
+            "crop-width" => {
+                let settings = self.settings.lock().unwrap();
+                settings.crop_width.to_value()
+            }
+            "crop-height" => {
+                let settings = self.settings.lock().unwrap();
+                settings.crop_height.to_value()
+            }
+            "scaling-mode" => {
+                let settings = self.settings.lock().unwrap();
+                settings.scaling_mode.to_value()
+            }
+            "freeze-on-pause" => {
+                let settings = self.settings.lock().unwrap();
+                settings.freeze_on_pause.to_value()
+            }
+            "on-unsupported-format" => {
+                let settings = self.settings.lock().unwrap();
+                settings.on_unsupported_format.to_value()
+            }
+            "adaptive-fps" => {
+                let settings = self.settings.lock().unwrap();
+                settings.adaptive_fps.to_value()
+            }
+            "min-fps" => {
+                let settings = self.settings.lock().unwrap();
+                settings.min_fps.to_value()
+            }
+            "target" => {
+                let settings = self.settings.lock().unwrap();
+                settings.target.to_value()
+            }
+            "fixed-output-size" => {
+                let settings = self.settings.lock().unwrap();
+                settings.fixed_output_size.to_value()
+            }
+            "smooth-timestamps" => {
+                let settings = self.settings.lock().unwrap();
+                settings.smooth_timestamps.to_value()
+            }
+            "ts-offset" => {
+                let settings = self.settings.lock().unwrap();
+                settings.ts_offset.to_value()
+            }
+            "even-dimensions" => {
+                let settings = self.settings.lock().unwrap();
+                settings.even_dimensions.to_value()
+            }
+            "detect-black-frames" => {
+                let settings = self.settings.lock().unwrap();
+                settings.detect_black_frames.to_value()
+            }
+            "black-frame-threshold" => {
+                let settings = self.settings.lock().unwrap();
+                settings.black_frame_threshold.to_value()
+            }
+            "fallback-on-stall" => {
+                let settings = self.settings.lock().unwrap();
+                settings.fallback_on_stall.to_value()
+            }
+            "stall-timeout-ms" => {
+                let settings = self.settings.lock().unwrap();
+                settings.stall_timeout_ms.to_value()
+            }
+            "deterministic-timing" => {
+                let settings = self.settings.lock().unwrap();
+                settings.deterministic_timing.to_value()
+            }
+            "start-retries" => {
+                let settings = self.settings.lock().unwrap();
+                settings.start_retries.to_value()
+            }
+            "start-retry-delay-ms" => {
+                let settings = self.settings.lock().unwrap();
+                settings.start_retry_delay_ms.to_value()
+            }
+            "capture-backend" => {
+                let state = self.state.lock().unwrap();
+                state.capture_backend.to_value()
+            }
+            "target-resolution" => {
+                let state = self.state.lock().unwrap();
+                state
+                    .target_resolution
+                    .map(|(width, height)| format!("{width}x{height}"))
+                    .to_value()
+            }
+            "capture-fps-actual" => {
+                let state = self.state.lock().unwrap();
+                state.actual_fps().to_value()
+            }
+            "capture-latency" => {
+                let state = self.state.lock().unwrap();
+                state.capture_latency.unwrap_or(0).to_value()
+            }
+            "target-kind" => {
+                let state = self.state.lock().unwrap();
+                state.target_kind.unwrap_or(TargetKind::Unknown).to_value()
+            }
+            "current-caps" => {
+                let state = self.state.lock().unwrap();
+                state
+                    .info
+                    .as_ref()
+                    .and_then(|info| info.to_caps().ok())
+                    .to_value()
+            }
+            // "select-target-cb" => {
+            //     let settings = self.settings.lock().unwrap();
+            //     settings.sel_target_cb.to_value()
+            // }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl GstObjectImpl for ScapSrc {}
+
+impl ElementImpl for ScapSrc {
+    fn metadata() -> Option<&'static gst::subclass::ElementMetadata> {
+        static ELEMENT_METADATA: LazyLock<gst::subclass::ElementMetadata> = LazyLock::new(|| {
+            gst::subclass::ElementMetadata::new(
+                "Scap screencast source",
+                "Source/Video",
+                "Scap screencast source",
+                "Marcus L. Hanestad <marlhan@proton.me>",
+            )
+        });
+
+        Some(&*ELEMENT_METADATA)
+    }
+
+    fn pad_templates() -> &'static [gst::PadTemplate] {
+        static PAD_TEMPLATES: LazyLock<Vec<gst::PadTemplate>> = LazyLock::new(|| {
+            let caps = gst_video::VideoCapsBuilder::new()
+                .format_list([
+                    gst_video::VideoFormat::Rgb,
+                    gst_video::VideoFormat::Rgbx,
+                    gst_video::VideoFormat::Xbgr,
+                    gst_video::VideoFormat::Bgrx,
+                    gst_video::VideoFormat::Bgra,
+                ])
+                .framerate_range(gst::Fraction::new(1, 1)..=gst::Fraction::new(i32::MAX, 1))
+                .build();
+            let src_pad_template = gst::PadTemplate::new(
+                "src",
+                gst::PadDirection::Src,
+                gst::PadPresence::Always,
+                &caps,
+            )
+            .unwrap();
+
+            vec![src_pad_template]
+        });
+
+        &PAD_TEMPLATES
+    }
+
+    fn change_state(
+        &self,
+        transition: gst::StateChange,
+    ) -> Result<gst::StateChangeSuccess, gst::StateChangeError> {
+        gst::debug!(CAT, imp = self, "State transition: {transition:?}");
+
+        // Capture has to be running before the base class lets `create()` run
+        if transition == gst::StateChange::PausedToPlaying {
+            self.start_playing()?;
+        }
+
+        let mut res = self.parent_change_state(transition)?;
+
+        match transition {
+            gst::StateChange::NullToReady => {}
+            gst::StateChange::ReadyToPaused => res = gst::StateChangeSuccess::NoPreroll,
+            gst::StateChange::PausedToPlaying => {}
+            gst::StateChange::PlayingToPaused => {
+                let freeze_on_pause = self.settings.lock().unwrap().freeze_on_pause;
+                let mut state = self.state.lock().unwrap();
+                state.playing = false;
+
+                if freeze_on_pause {
+                    state.frozen = true;
+                    // Skip the time spent paused once playing again
+                    state.resume_pending = true;
+                    gst::info!(CAT, imp = self, "Capture was frozen");
+                }
+                drop(state);
+
+                self.set_streaming(false);
+            }
+            gst::StateChange::PausedToReady => {}
+            gst::StateChange::ReadyToNull => {}
+            gst::StateChange::NullToNull => {}
+            gst::StateChange::ReadyToReady => {}
+            gst::StateChange::PausedToPaused => {}
+            gst::StateChange::PlayingToPlaying => {}
+        }
+
+        Ok(res)
+    }
+}
+
+impl BaseSrcImpl for ScapSrc {
+    fn start(&self) -> Result<(), gst::ErrorMessage> {
+        // Not holding the lock as setting caps during preroll queries the caps
+        let settings = self.settings.lock().unwrap().clone();
+
+        if let Some(capture) = self.capture.lock().unwrap().take() {
+            gst::debug!(CAT, imp = self, "Capturer exists, stopping");
+            capture.stop();
+        }
+        self.frames.reset();
+        self.set_streaming(false);
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.base_time = None;
+            // Only once per start, so resuming from pause doesn't discard
+            // frames and restart timestamps again
+            state.pending_startup_delay = (settings.startup_delay_ms > 0)
+                .then(|| Duration::from_millis(settings.startup_delay_ms.into()));
+            state.startup_deadline = None;
+            state.qos_earliest_time = None;
+            state.qos_proportion = None;
+            state.dropped = 0;
+            state.last_pts = None;
+            state.frame_count = 0;
+            state.last_heartbeat_pts = 0;
+            state.converter = None;
+            state.last_capture_time = None;
+            state.avg_capture_interval = None;
+            state.capture_latency = None;
+            state.reported_capture_latency = 0;
+            state.latency_message_time = None;
+            state.requested_info = None;
+            state.resume_pending = false;
+            state.target_resolution = None;
+            state.frozen = false;
+            state.recent_pts.clear();
+            state.tags_sent = false;
+            state.adaptive_fps = None;
+            state.throttle_deadline = None;
+            state.avg_processing_time = None;
+            state.target_kind = None;
+            state.fixed_output_size = None;
+            state.captured_frames = 0;
+            state.pacing_start = None;
+            state.black_frames = 0;
+            state.context = None;
+            state.caps = None;
+            state.stall_frame = None;
+        }
+
+        // TODO: Use settings.sel_target_cb to select the target
+        // On Linux the target is picked through the portal when the capturer
+        // is built, so scap never lists any
+        if !cfg!(target_os = "linux") && sorted_targets().is_empty() {
+            return Err(gst::error_msg!(
+                gst::ResourceError::NotFound,
+                ["No capture targets available"]
+            ));
+        }
+
+        if cfg!(target_os = "linux") && settings.target != DEFAULT_TARGET {
+            gst::warning!(
+                CAT,
+                imp = self,
+                "Can't select target `{}` on Linux, picking it through the portal",
+                settings.target,
+            );
+        }
+
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            state.starting = true;
+            state.generation += 1;
+            self.generation_changed.notify_all();
+            state.generation
+        };
+
+        // Building the capturer can block for a long time, e.g. while the portal
+        // asks what to share, so finish starting on another thread instead of
+        // blocking the state change
+        let element = self.obj().clone();
+        let mut start_thread = self.start_thread.lock().unwrap();
+        let previous_start_thread = start_thread.take();
+        *start_thread = Some(thread::spawn(move || {
+            // Only one capturer may run at a time, so the one of an earlier
+            // start has to be discarded first
+            if let Some(previous_start_thread) = previous_start_thread {
+                let _ = previous_start_thread.join();
+            }
+
+            let imp = element.imp();
+            let res = imp.start_capturer(&settings, generation);
+
+            let mut state = imp.state.lock().unwrap();
+            if state.generation != generation {
+                return;
+            }
+            match res {
+                Ok(true) => {
+                    drop(state);
+                    element.start_complete(gst::FlowReturn::Ok);
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    state.starting = false;
+                    drop(state);
+                    imp.post_error_message(err);
+                    element.start_complete(gst::FlowReturn::Error);
+                }
+            }
+        }));
+
+        Ok(())
+    }
+
+    fn stop(&self) -> Result<(), gst::ErrorMessage> {
+        // Held until the capturer is stopped, so no other one is built before
+        let mut capture = self.capture.lock().unwrap();
+        let starting = {
+            let mut state = self.state.lock().unwrap();
+            // A capturer still being built is discarded by its start thread
+            state.generation += 1;
+            self.generation_changed.notify_all();
+            std::mem::take(&mut state.starting)
+        };
+
+        match capture.take() {
+            Some(c) => c.stop(),
+            None if starting => {}
+            None => {
+                return Err(gst::error_msg!(
+                    gst::LibraryError::Shutdown,
+                    ["Missing capturer"]
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Wakes up `create()` waiting for a frame, which stays queued for the next
+    // call
+    fn unlock(&self) -> Result<(), gst::ErrorMessage> {
+        gst::debug!(CAT, imp = self, "Unlocking");
+        self.frames.set_flushing(true);
+        Ok(())
+    }
+
+    fn unlock_stop(&self) -> Result<(), gst::ErrorMessage> {
+        gst::debug!(CAT, imp = self, "Unlock stopped");
+        self.frames.set_flushing(false);
+
+        // QoS from before flushing doesn't apply to the frames after it
+        let mut state = self.state.lock().unwrap();
+        state.qos_earliest_time = None;
+        state.qos_proportion = None;
+
+        Ok(())
+    }
+
+    fn set_caps(&self, caps: &gst::Caps) -> Result<(), gst::LoggableError> {
+        let info = gst_video::VideoInfo::from_caps(caps).map_err(|_| {
+            gst::loggable_error!(CAT, "Failed to build `VideoInfo` from caps {}", caps)
+        })?;
+
+        gst::debug!(CAT, imp = self, "Configuring for caps {}", caps);
+
+        let (new_width, new_height) = (info.width(), info.height());
+
+        // Only informational, `create()` always produces exactly one whole
+        // frame per buffer regardless of the blocksize
+        self.obj().set_blocksize(info.size() as u32);
+
+        let target = self.settings.lock().unwrap().target.clone();
+        let mut state = self.state.lock().unwrap();
+
+        let mut context = gst::Context::new(CAPTURE_CONTEXT_TYPE, false);
+        {
+            let s = context.get_mut().unwrap().structure_mut();
+            s.set("width", new_width);
+            s.set("height", new_height);
+            s.set("framerate", info.fps());
+            s.set("target", target);
+            s.set(
+                "target-kind",
+                state.target_kind.unwrap_or(TargetKind::Unknown),
+            );
+        }
+
+        state.info = Some(info);
+        state.caps = Some(caps.clone());
+        state.width = new_width as i32;
+        state.height = new_height as i32;
+        state.context = Some(context.clone());
+        drop(state);
+
+        self.obj().notify("current-caps");
+
+        // Bins hand the context to elements added later, the others can query
+        // for it
+        let _ = self.obj().post_message(
+            gst::message::HaveContext::builder(context)
+                .src(&*self.obj())
+                .build(),
+        );
+
+        Ok(())
+    }
+
+    fn negotiate(&self) -> Result<(), gst::LoggableError> {
+        let src_pad = self.obj().src_pad();
+        let our_caps = src_pad.query_caps(None);
+        let peer_caps = src_pad.peer_query_caps(Some(&our_caps));
+
+        // Intersect in our order so the format scap captures in is picked if
+        // downstream accepts it, avoiding a conversion
+        let mut caps = our_caps.intersect_with_mode(&peer_caps, gst::CapsIntersectMode::First);
+        if caps.is_empty() {
+            return Err(gst::loggable_error!(
+                CAT,
+                "No caps in common with downstream {peer_caps}"
+            ));
+        }
+
+        let caps = self.fixate(caps);
+
+        gst::debug!(CAT, imp = self, "Negotiated caps {caps}");
+
+        self.obj()
+            .set_caps(&caps)
+            .map_err(|err| gst::loggable_error!(CAT, "Failed to set caps {caps}: {err}"))
+    }
+
+    fn decide_allocation(
+        &self,
+        query: &mut gst::query::Allocation,
+    ) -> Result<(), gst::LoggableError> {
+        let video_meta_supported = query
+            .find_allocation_meta::<gst_video::VideoMeta>()
+            .is_some();
+
+        gst::debug!(
+            CAT,
+            imp = self,
+            "Downstream supports video meta: {video_meta_supported}"
+        );
+
+        self.state.lock().unwrap().video_meta_supported = video_meta_supported;
+
+        self.parent_decide_allocation(query)
+    }
+
+    fn fixate(&self, mut caps: gst::Caps) -> gst::Caps {
+        let settings = self.settings.lock().unwrap().clone();
+        let fps = settings.output_fps();
+        let target_resolution = self.state.lock().unwrap().target_resolution;
+
+        // Prefer what will be captured, so the first frame is less likely to
+        // need a renegotiation
+        let (width, height) = match target_resolution {
+            Some((width, height)) => settings.output_size_for(width, height),
+            None => (settings.output_width, settings.output_height),
+        };
+
+        caps.truncate();
+        {
+            let caps = caps.make_mut();
+            let s = caps.structure_mut(0).unwrap();
+            s.fixate_field_nearest_fraction("framerate", gst::Fraction::new(fps as i32, 1));
+            if width > 0 {
+                s.fixate_field_nearest_int("width", width as i32);
+            }
+            if height > 0 {
+                s.fixate_field_nearest_int("height", height as i32);
+            }
+        }
+
+        self.parent_fixate(caps)
+    }
+
+    fn caps(&self, filter: Option<&gst::Caps>) -> Option<gst::Caps> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        // Once known, only the size frames are delivered at can be produced,
+        // and after the first frame only the format it came in
+        let (output_size, output_format) = {
+            let state = self.state.lock().unwrap();
+            match (&state.requested_info, state.target_resolution) {
+                (Some(info), _) => (Some((info.width(), info.height())), Some(info.format())),
+                (None, Some((width, height))) => {
+                    (Some(settings.output_size_for(width, height)), None)
+                }
+                (None, None) => (None, None),
+            }
+        };
+
+        let size_range = |max: u32| match max {
+            0 => 1..=i32::MAX,
+            max => 1..=max as i32,
+        };
+        let caps_builder = || {
+            let builder = gst_video::VideoCapsBuilder::new()
+                .framerate_range(gst::Fraction::new(1, 1)..=gst::Fraction::new(i32::MAX, 1));
+            match output_size {
+                Some((width, height)) => builder.width(width as i32).height(height as i32),
+                None => builder
+                    .width_range(size_range(settings.max_width))
+                    .height_range(size_range(settings.max_height)),
+            }
+        };
+        let mut caps = match output_format {
+            Some(format) => caps_builder().format(format).build(),
+            None if cfg!(target_os = "linux") => {
+                caps_builder().format_list(PIPEWIRE_FORMATS).build()
+            }
+            None => caps_builder()
+                .format(settings.output_format.video_format())
+                .build(),
+        };
+        if settings.auto_convert {
+            caps.merge(caps_builder().build());
+        }
+
+        gst::debug!(CAT, imp = self, "Advertising caps {caps}");
+
+        Some(match filter {
+            Some(filter) => filter.intersect_with_mode(&caps, gst::CapsIntersectMode::First),
+            None => caps,
+        })
+    }
+
+    // Seeks and steps are rejected as there is nothing to go back to and a
+    // live source can't hand out frames while paused
+    fn event(&self, event: &gst::Event) -> bool {
+        match event.view() {
+            gst::EventView::Reconfigure(_) => {
+                // The base class renegotiates before the next `create()`, after
+                // which the next frame has to be checked against the new caps
+                gst::debug!(CAT, imp = self, "Downstream asked to reconfigure");
+                self.state.lock().unwrap().requested_info = None;
+            }
+            gst::EventView::Qos(qos) => {
+                let (_, proportion, diff, timestamp) = qos.get();
+
+                let mut state = self.state.lock().unwrap();
+                state.qos_earliest_time = timestamp.map(|ts| {
+                    gst::ClockTime::from_nseconds(ts.nseconds().saturating_add_signed(diff))
+                });
+                state.qos_proportion = Some(proportion);
+
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "QoS: proportion {proportion}, earliest time {}",
+                    state.qos_earliest_time.display(),
+                );
+            }
+            gst::EventView::Seek(_) | gst::EventView::Step(_) => {
+                gst::debug!(
+                    CAT,
+                    imp = self,
+                    "Ignoring {:?} event, capturing is live",
+                    event.type_(),
+                );
+                return false;
+            }
+            _ => {}
+        }
+
+        BaseSrcImplExt::parent_event(self, event)
+    }
+
+    fn query(&self, query: &mut gst::QueryRef) -> bool {
+        use gst::QueryViewMut;
+        let settings = self.settings.lock().unwrap();
+        match query.view_mut() {
+            QueryViewMut::Latency(q) => {
+                let fps = settings.output_fps();
+                drop(settings);
+
+                let mut state = self.state.lock().unwrap();
+                let capture_interval = state
+                    .avg_capture_interval
+                    .unwrap_or_else(|| gst::ClockTime::SECOND.nseconds() / fps as u64);
+                state.reported_capture_latency = state.capture_latency.unwrap_or(0);
+                let min_latency = gst::ClockTime::from_nseconds(
+                    capture_interval + state.reported_capture_latency,
+                );
+                drop(state);
+
+                gst::debug!(CAT, imp = self, "Reporting latency of {min_latency}");
+
+                q.set(true, min_latency, gst::ClockTime::NONE);
+                true
+            }
+            QueryViewMut::Context(q) if q.context_type() == CAPTURE_CONTEXT_TYPE => {
+                drop(settings);
+
+                match self.state.lock().unwrap().context.clone() {
+                    Some(context) => {
+                        q.set_context(&context);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => {
+                drop(settings);
+                BaseSrcImplExt::parent_query(self, query)
+            }
+        }
+    }
+}
+
+impl PushSrcImpl for ScapSrc {
+    // Frames are always returned in new buffers sized for them, never in one
+    // from the downstream pool, so a frame larger than what the pool was set
+    // up for can't overflow it. The caps are updated for such frames in
+    // `ensure_correct_format()`.
+    fn create(&self, _: Option<&mut gst::BufferRef>) -> Result<CreateSuccess, gst::FlowError> {
+        // Taken again only while paused, as the loop below keeps running then
+        let mut settings = self.settings.lock().unwrap().frame_settings();
+
+        // The state stays locked from checking a frame until it's converted
+        let mut retries = 0;
+        let (frame, frame_info, pts, queue_dropped, mut state) = loop {
+            let (queued, stalled) = match self.next_frame(settings.stall_timeout)? {
+                Some(queued) => (queued, false),
+                None => {
+                    // Nothing to take the format and size from until the first
+                    // frame arrived
+                    let Some((_, frame)) = self.state.lock().unwrap().stall_frame.clone() else {
+                        continue;
+                    };
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "No frame captured in time, delivering a black one"
+                    );
+                    let queued = QueuedFrame {
+                        frame: Ok(with_display_time(frame, capture_clock_now())),
+                        dropped: 0,
+                        preroll: false,
+                    };
+                    (queued, true)
+                }
+            };
+            let frame = match queued.frame {
+                Ok(frame) => frame,
+                Err(err) if retries < settings.max_retries => {
+                    retries += 1;
+                    self.post_scap_error(
+                        ScapErrorCode::Transient,
+                        format!("Failed to get next frame: {err}"),
+                    );
+                    gst::warning!(
+                        CAT,
+                        imp = self,
+                        "Failed to get next frame: {err}. Restarting capturer (attempt {retries}/{})",
+                        settings.max_retries,
+                    );
+
+                    if let Err(err) = self.rebuild_capturer() {
+                        self.post_error_message(err);
+                        return Err(gst::FlowError::Error);
+                    }
+
+                    continue;
+                }
+                Err(err) => {
+                    // scap only fails once the capture engine is gone
+                    self.post_scap_error(
+                        ScapErrorCode::TargetLost,
+                        format!("Failed to get next frame: {err}"),
+                    );
+                    gst::element_error!(
+                        self.obj(),
+                        gst::ResourceError::Read,
+                        ("Failed to get next frame: {err}")
+                    );
+                    return Err(gst::FlowError::Error);
+                }
+            };
+
+            let frame_info =
+                FrameInfo::new(&frame).or_else(|| match settings.on_unsupported_format {
+                    UnsupportedFormatAction::Convert => FrameInfo::new_convertible(&frame),
+                    _ => None,
+                });
+            let Some(frame_info) = frame_info else {
+                if settings.on_unsupported_format == UnsupportedFormatAction::Skip {
+                    gst::debug!(CAT, imp = self, "Skipping frame in unsupported format");
+                    let mut state = self.state.lock().unwrap();
+                    state.captured_frames += 1;
+                    state.dropped += 1;
+                    continue;
+                }
+
+                self.post_scap_error(
+                    ScapErrorCode::Fatal,
+                    "Unsupported frame format received".to_string(),
+                );
+                gst::element_error!(
+                    self.obj(),
+                    gst::ResourceError::Failed,
+                    ("Unsupported frame format received")
+                );
+                return Err(gst::FlowError::Error);
+            };
+
+            let fps = settings.fps;
+            let mut state = self.state.lock().unwrap();
+
+            // Black frames only stand in for captured ones, so they don't
+            // count towards how often and how late frames are captured
+            if !stalled {
+                if let Some(last_capture_time) = state.last_capture_time {
+                    let interval = frame_info.pts.saturating_sub(last_capture_time);
+                    state.avg_capture_interval = Some(match state.avg_capture_interval {
+                        Some(avg) => (avg * 7 + interval) / 8,
+                        None => interval,
+                    });
+                }
+                state.last_capture_time = Some(frame_info.pts);
+
+                // Anything above a second means scap used another clock than
+                // expected
+                let latency = capture_clock_now().saturating_sub(frame_info.pts);
+                if latency < gst::ClockTime::SECOND.nseconds() {
+                    state.capture_latency = Some(match state.capture_latency {
+                        Some(avg) => (avg * 7 + latency) / 8,
+                        None => latency,
+                    });
+                }
+
+                let layout = (frame_info.gst_v_format, frame_info.width, frame_info.height);
+                if settings.stall_timeout.is_some()
+                    && !matches!(&state.stall_frame, Some((l, _)) if *l == layout)
+                {
+                    state.stall_frame = Some((layout, black_frame_like(&frame)));
+                }
+            }
+
+            if let Some(deadline) = state.startup_deadline {
+                if Instant::now() < deadline {
+                    continue;
+                }
+
+                gst::debug!(CAT, imp = self, "Startup delay elapsed");
+                state.startup_deadline = None;
+                state.base_time = Some(frame_info.pts as i64);
+            }
+
+            if settings.paused {
+                state.resume_pending = true;
+                drop(state);
+                // Checked for every frame until resumed
+                settings = self.settings.lock().unwrap().frame_settings();
+                continue;
+            }
+
+            // Frames the queue dropped as `create()` fell behind were
+            // captured before this one and count like the other drops
+            if !queued.preroll {
+                state.captured_frames += 1 + queued.dropped;
+                state.dropped += queued.dropped;
+            }
+
+            if state.resume_pending {
+                // Skip the time spent paused or since the preroll frame
+                state.rebase(frame_info.pts, fps);
+                state.resume_pending = false;
+                gst::debug!(CAT, imp = self, "Resumed capture");
+            }
+
+            if let Some(base_time) = state.base_time.filter(|&t| (frame_info.pts as i64) < t) {
+                gst::warning!(
+                    CAT,
+                    imp = self,
+                    "Frame captured at {} is older than the base time {base_time} ns, rebasing",
+                    gst::ClockTime::from_nseconds(frame_info.pts),
+                );
+            }
+
+            // Frames aren't dropped with deterministic timing, as pacing
+            // already keeps to the frame rate and timestamps don't follow
+            // the clock
+            if settings.deterministic_timing {
+                let pts = state.counter_pts(fps);
+                break (frame, frame_info, pts, queued.dropped, state);
+            }
+
+            let mut pts = state.frame_pts(frame_info.pts, fps);
+            if settings.smooth_timestamps {
+                pts = state.snap_pts(pts, fps);
+            }
+
+            // QoS is in running time, which the buffers get with ts-offset
+            let running_time = pts.saturating_add_signed(settings.ts_offset);
+            if let Some(earliest_time) = state.qos_earliest_time {
+                if running_time < earliest_time.nseconds() {
+                    state.dropped += 1;
+                    gst::debug!(
+                        CAT,
+                        imp = self,
+                        "Dropping late frame with running time {}, earliest time is {earliest_time}",
+                        gst::ClockTime::from_nseconds(running_time),
+                    );
+
+                    let jitter = (earliest_time.nseconds() - running_time) as i64;
+                    let (processed, dropped) = (state.frame_count + state.dropped, state.dropped);
+                    drop(state);
+                    self.post_qos(running_time, jitter, processed, dropped);
+                    continue;
+                }
+            }
+
+            let adaptive_fps = state.adaptive_fps.filter(|_| settings.adaptive_fps);
+            // Downstream taking longer than real time can only keep up with
+            // a rate lowered by the same proportion
+            let qos_fps = state
+                .qos_proportion
+                .filter(|&proportion| proportion > 1.0)
+                .map(|proportion| ((fps as f64 / proportion) as u32).max(1));
+            let max_fps = [
+                Some(settings.max_fps).filter(|&fps| fps > 0),
+                adaptive_fps,
+                qos_fps,
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            if let Some(max_fps) = max_fps {
+                if !state.throttle(pts, max_fps) {
+                    state.dropped += 1;
+                    gst::trace!(
+                        CAT,
+                        imp = self,
+                        "Dropping frame with pts {} to stay below {max_fps} fps",
+                        gst::ClockTime::from_nseconds(pts),
+                    );
+
+                    let (processed, dropped) = (state.frame_count + state.dropped, state.dropped);
+                    drop(state);
+                    self.post_qos(running_time, 0, processed, dropped);
+                    continue;
+                }
+            }
+
+            // The preroll frame was captured before going to playing, so the
+            // next frame follows it instead of the time in between
+            if queued.preroll {
+                state.resume_pending = true;
+            }
+
+            break (frame, frame_info, pts, queued.dropped, state);
+        };
+
+        let fps = settings.fps;
+
+        if let Some(duration) = settings.duration {
+            if pts > duration.nseconds() {
+                gst::debug!(CAT, imp = self, "Reached configured duration {duration}");
+                return Err(gst::FlowError::Eos);
+            }
+        }
+
+        let processing_start = Instant::now();
+
+        // Reported like the frames dropped for QoS above
+        let queue_qos = (queue_dropped > 0).then(|| {
+            (
+                pts.saturating_add_signed(settings.ts_offset),
+                state.frame_count + state.dropped,
+                state.dropped,
+            )
+        });
+
+        let pacing_deadline = settings
+            .deterministic_timing
+            .then(|| state.pacing_deadline(pts, fps, Instant::now()));
+
+        // Keep the size the stream started with and scale later frames to it
+        // instead of renegotiating
+        if settings.fixed_output_size {
+            let (width, height) = *state.fixed_output_size.get_or_insert_with(|| {
+                settings
+                    .sizing
+                    .size_for(frame_info.width, frame_info.height)
+            });
+            settings.sizing.width = width;
+            settings.sizing.height = height;
+        }
+
+        let latency_changed = state.capture_latency_changed(Instant::now());
+
+        // Frames that need converting are negotiated in the output format
+        let negotiated_frame_info = FrameInfo {
+            gst_v_format: match frame_info.needs_conversion {
+                true => settings.output_format.video_format(),
+                false => frame_info.gst_v_format,
+            },
+            ..frame_info
+        };
+
+        let (mut state, caps_match) =
+            self.ensure_correct_format(state, &negotiated_frame_info, &settings)?;
+
+        let captured_info = frame_info.video_info(fps).map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to create video info: {err}");
+            gst::FlowError::Error
+        })?;
+
+        let mut buffer = frame_to_buffer(frame);
+        add_packed_video_meta(buffer.get_mut().unwrap(), &captured_info).map_err(|err| {
+            gst::error!(CAT, imp = self, "Failed to add video meta: {err}");
+            gst::FlowError::Error
+        })?;
+
+        let black = settings.detect_black_frames && is_black_frame(&buffer, &captured_info);
+
+        let tags_backend =
+            (!std::mem::replace(&mut state.tags_sent, true)).then_some(state.capture_backend);
+
+        // Only the run reaching the threshold is reported
+        state.black_frames = if black {
+            state.black_frames.saturating_add(1)
+        } else {
+            0
+        };
+        let black_frames =
+            (state.black_frames == settings.black_frame_threshold).then_some(state.black_frames);
+
+        state.last_pts = Some(pts);
+        state.frame_count += 1;
+
+        state.record_delivery(pts);
+
+        let interval = gst::ClockTime::from_seconds(settings.heartbeat_interval.into()).nseconds();
+        let heartbeat_frame_count = if settings.heartbeat_interval > 0
+            && pts.saturating_sub(state.last_heartbeat_pts) >= interval
+        {
+            state.last_heartbeat_pts = pts;
+            Some(state.frame_count)
+        } else {
+            None
+        };
+
+        let video_meta_info = state
+            .video_meta_supported
+            .then(|| state.info.clone())
+            .flatten();
+        let sequence = state.captured_frames.saturating_sub(1);
+        // Only needed for a frame requested with `try_pull_frame()`
+        let caps = (self.waiting_frame_requests.load(Ordering::SeqCst) > 0)
+            .then(|| state.caps.clone())
+            .flatten();
+
+        if settings.auto_convert
+            || settings.sizing.is_set()
+            || frame_info.needs_conversion
+            || !caps_match
+        {
+            buffer = self.convert_to_negotiated(
+                &mut state,
+                buffer,
+                &frame_info,
+                fps,
+                settings.scaling_mode,
+            )?;
+        }
+
+        if settings.adaptive_fps {
+            self.adapt_fps(&mut state, processing_start.elapsed(), &settings);
+        }
+
+        // The size of the buffer, which was converted to the negotiated one
+        // if it differed from the captured one
+        let (output_width, output_height) = state
+            .info
+            .as_ref()
+            .map_or((frame_info.width, frame_info.height), |info| {
+                (info.width(), info.height())
+            });
+
+        drop(state);
+
+        // End before capturing again when the next frame can't be within the
+        // duration, so no caps are negotiated for a frame that is never pushed
+        let frame_duration = gst::ClockTime::SECOND.nseconds() / fps as u64;
+        if settings
+            .duration
+            .is_some_and(|duration| pts + frame_duration > duration.nseconds())
+        {
+            self.frames.set_eos();
+        }
+
+        if let Some((running_time, processed, dropped)) = queue_qos {
+            self.post_qos(running_time, 0, processed, dropped);
+        }
+
+        if latency_changed {
+            let _ = self
+                .obj()
+                .post_message(gst::message::Latency::builder().src(&*self.obj()).build());
+        }
+
+        if let Some(backend) = tags_backend {
+            // Queued by the base class and pushed after the segment
+            self.obj()
+                .send_event(gst::event::Tag::new(Self::capture_tags(backend)));
+        }
+
+        if let Some(black_frames) = black_frames {
+            gst::element_warning!(
+                self.obj(),
+                gst::ResourceError::Read,
+                ("Captured {black_frames} black frames in a row"),
+                ["Capturing may have failed without scap reporting an error"]
+            );
+        }
+
+        if let Some(frame_count) = heartbeat_frame_count {
+            let s = gst::Structure::builder("scapsrc-heartbeat")
+                .field("running-time", gst::ClockTime::from_nseconds(pts))
+                .field("frame-count", frame_count)
+                .build();
+            let _ = self.obj().post_message(
+                gst::message::Application::builder(s)
+                    .src(&*self.obj())
+                    .build(),
+            );
+        }
+
+        // Buffers straight from scap with a layout only the meta describes
+        let has_video_meta = buffer.meta::<gst_video::VideoMeta>().is_some();
+        if has_video_meta && video_meta_info.is_none() {
+            buffer = repack(buffer, &captured_info).map_err(|err| {
+                gst::error!(CAT, imp = self, "Failed to repack frame: {err}");
+                gst::FlowError::Error
+            })?;
+        }
+
+        let buf = buffer.get_mut().unwrap();
+        buf.set_pts(gst::ClockTime::from_nseconds(
+            pts.saturating_add_signed(settings.ts_offset),
+        ));
+        buf.set_duration(gst::ClockTime::SECOND / fps as u64);
+        // Gaps in the offsets downstream are frames that were dropped
+        buf.set_offset(sequence);
+        buf.set_offset_end(sequence + 1);
+        gst::ReferenceTimestampMeta::add(
+            buf,
+            &CAPTURE_TIME_CAPS,
+            gst::ClockTime::from_nseconds(frame_info.pts),
+            gst::ClockTime::NONE,
+        );
+
+        if let Some(info) = video_meta_info.filter(|_| !has_video_meta) {
+            if let Err(err) = gst_video::VideoMeta::add(
+                buf,
+                gst_video::VideoFrameFlags::empty(),
+                info.format(),
+                info.width(),
+                info.height(),
+            ) {
+                gst::warning!(CAT, imp = self, "Failed to add video meta: {err}");
+            }
+        }
+
+        if settings.emit_signals {
+            self.obj()
+                .emit_by_name::<()>("on-frame", &[&pts, &output_width, &output_height]);
+        }
+
+        if let Some(caps) = caps {
+            let sample = gst::Sample::builder().buffer(&buffer).caps(&caps).build();
+            let mut requests = self.frame_requests.lock().unwrap();
+            requests.sample = Some(sample);
+            requests.serial += 1;
+            drop(requests);
+            self.frame_produced.notify_all();
+        }
+
+        if let Some(deadline) = pacing_deadline {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
 
         Ok(CreateSuccess::NewBuffer(buffer))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = gst::ClockTime::SECOND.nseconds();
+
+    #[test]
+    fn first_frame_latches_base_time() {
+        let mut state = State::default();
+
+        assert_eq!(state.frame_pts(5 * SECOND, 25), 0);
+        assert_eq!(state.base_time, Some(5 * SECOND as i64));
+        assert_eq!(state.frame_pts(6 * SECOND, 25), SECOND);
+    }
+
+    #[test]
+    fn older_frame_follows_last_pts() {
+        let mut state = State::default();
+        state.frame_pts(10 * SECOND, 25);
+        state.last_pts = Some(SECOND);
+
+        // Capture clock went backwards
+        assert_eq!(state.frame_pts(2 * SECOND, 25), SECOND + SECOND / 25);
+        assert_eq!(state.frame_pts(3 * SECOND, 25), 2 * SECOND + SECOND / 25);
+    }
+
+    #[test]
+    fn clock_reset_below_last_pts() {
+        let mut state = State::default();
+        state.frame_pts(10 * SECOND, 25);
+        state.last_pts = Some(5 * SECOND);
+
+        // Capture clock restarted at a time below the last pts
+        assert_eq!(state.frame_pts(2 * SECOND, 25), 5 * SECOND + SECOND / 25);
+        state.last_pts = Some(5 * SECOND + SECOND / 25);
+        assert_eq!(
+            state.frame_pts(2 * SECOND + SECOND / 25, 25),
+            5 * SECOND + 2 * SECOND / 25
+        );
+    }
+
+    #[test]
+    fn rebase_skips_gap() {
+        let mut state = State::default();
+        state.frame_pts(SECOND, 10);
+        state.last_pts = Some(SECOND);
+
+        // Resumed 60 seconds later
+        state.rebase(62 * SECOND, 10);
+        assert_eq!(state.frame_pts(62 * SECOND, 10), SECOND + SECOND / 10);
+    }
+
+    #[test]
+    fn deterministic_timing_pts_and_pacing() {
+        let mut state = State::default();
+        state.frame_count = 3;
+        assert_eq!(state.counter_pts(30), SECOND);
+
+        let start = Instant::now();
+        let frame_duration = Duration::from_nanos(SECOND / 30);
+        assert_eq!(state.pacing_deadline(SECOND, 30, start), start);
+
+        // Frames arriving early wait for their slot
+        let deadline = state.pacing_deadline(SECOND + SECOND / 30, 30, start);
+        assert_eq!(deadline, start + frame_duration);
+
+        // Falling more than a frame behind starts pacing over
+        let late = start + 10 * frame_duration;
+        assert_eq!(state.pacing_deadline(SECOND + SECOND / 15, 30, late), late);
+    }
+
+    #[test]
+    fn capture_latency_change_is_relative_and_rate_limited() {
+        let mut state = State::default();
+        let start = Instant::now();
+        let msecond = gst::ClockTime::MSECOND.nseconds();
+
+        state.capture_latency = Some(50 * msecond);
+        assert!(state.capture_latency_changed(start));
+
+        // Within a tenth of the reported latency
+        state.capture_latency = Some(54 * msecond);
+        assert!(!state.capture_latency_changed(start + LATENCY_MESSAGE_INTERVAL));
+
+        // Moved enough, but only reported once the interval passed
+        state.capture_latency = Some(60 * msecond);
+        assert!(!state.capture_latency_changed(start + LATENCY_MESSAGE_INTERVAL / 2));
+        assert!(state.capture_latency_changed(start + LATENCY_MESSAGE_INTERVAL));
+        assert_eq!(state.reported_capture_latency, 60 * msecond);
+    }
+
+    #[test]
+    fn caps_fit_flapping_sizes() {
+        let info = |format, width, height| {
+            gst_video::VideoInfo::builder(format, width, height)
+                .fps(gst::Fraction::new(25, 1))
+                .build()
+                .unwrap()
+        };
+        let negotiate = |state: &mut State, info: &gst_video::VideoInfo| {
+            state.info = Some(info.clone());
+            state.width = info.width() as i32;
+            state.height = info.height() as i32;
+            state.requested_info = Some(info.clone());
+        };
+        let large = info(gst_video::VideoFormat::Bgrx, 1920, 1080);
+        let small = info(gst_video::VideoFormat::Bgrx, 1280, 720);
+
+        let mut state = State::default();
+        assert_eq!(state.caps_fit(&large, false), None);
+
+        negotiate(&mut state, &large);
+        assert_eq!(state.caps_fit(&large, false), Some(true));
+
+        // Every change of size needs new caps, but repeats of it don't
+        for _ in 0..3 {
+            assert_eq!(state.caps_fit(&small, false), Some(false));
+            negotiate(&mut state, &small);
+            assert_eq!(state.caps_fit(&small, false), Some(true));
+
+            assert_eq!(state.caps_fit(&large, false), Some(false));
+            negotiate(&mut state, &large);
+            assert_eq!(state.caps_fit(&large, false), Some(true));
+        }
+
+        // Frames are converted to the negotiated format with `auto-convert`
+        let rgb = info(gst_video::VideoFormat::Rgb, 1920, 1080);
+        assert_eq!(state.caps_fit(&rgb, false), Some(false));
+        assert_eq!(state.caps_fit(&rgb, true), Some(true));
+        assert_eq!(state.requested_info, Some(rgb));
+    }
+
+    #[test]
+    fn target_id_without_handle() {
+        assert_eq!(
+            without_handle("window:Issue #5 - Tracker#42"),
+            "window:Issue #5 - Tracker"
+        );
+        assert_eq!(without_handle("display:Built-in"), "display:Built-in");
+    }
+
+    #[test]
+    fn throttle_to_max_fps() {
+        let mut state = State::default();
+        let interval = SECOND / 30;
+
+        // Of 90 fps, the first frame within half an interval of each slot is
+        // delivered
+        let delivered = (0..12)
+            .filter(|i| state.throttle(i * SECOND / 90, 30))
+            .collect::<Vec<_>>();
+        assert_eq!(delivered, [0, 2, 5, 8, 11]);
+
+        // Frames at about 30 fps are all delivered despite jitter
+        let mut state = State::default();
+        assert!(state.throttle(0, 30));
+        assert!(state.throttle(interval - 2_000_000, 30));
+        assert!(state.throttle(2 * interval + 2_000_000, 30));
+        assert!(state.throttle(3 * interval - 2_000_000, 30));
+
+        // A second frame within the same interval is dropped
+        assert!(!state.throttle(3 * interval + 2_000_000, 30));
+    }
+
+    #[test]
+    fn snap_pts_to_grid() {
+        let mut state = State::default();
+        let frame_duration = SECOND / 25;
+
+        assert_eq!(
+            state.snap_pts(frame_duration + 3_000_000, 25),
+            frame_duration
+        );
+        assert_eq!(
+            state.snap_pts(frame_duration - 3_000_000, 25),
+            frame_duration
+        );
+
+        // A frame snapping to the slot of the last one moves to the next slot
+        state.last_pts = Some(frame_duration);
+        assert_eq!(
+            state.snap_pts(frame_duration + 5_000_000, 25),
+            2 * frame_duration
+        );
+
+        // Dropped frames leave a gap
+        assert_eq!(
+            state.snap_pts(4 * frame_duration + 1, 25),
+            4 * frame_duration
+        );
+    }
+
+    #[test]
+    fn packed_stride_of_odd_width() {
+        gst::init().unwrap();
+        let info = |format| {
+            gst_video::VideoInfo::builder(format, 1366, 2)
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(
+            packed_stride(&info(gst_video::VideoFormat::Rgb)),
+            Some(1366 * 3)
+        );
+        assert_eq!(packed_stride(&info(gst_video::VideoFormat::Bgrx)), None);
+    }
+
+    #[test]
+    fn black_frame_detection() {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgb, 1366, 768)
+            .build()
+            .unwrap();
+        let row = 1366 * 3;
+
+        let mut data = vec![0; row * 768];
+        assert!(is_black_frame(
+            &gst::Buffer::from_slice(data.clone()),
+            &info
+        ));
+
+        // A bright pixel in the middle of the frame is sampled
+        let (x, y) = (9 * 1366 / 16, 9 * 768 / 16);
+        data[y * row + x * 3 + 1] = 200;
+        assert!(!is_black_frame(&gst::Buffer::from_slice(data), &info));
+    }
+
+    #[test]
+    fn repack_pads_rows() {
+        gst::init().unwrap();
+        let info = gst_video::VideoInfo::builder(gst_video::VideoFormat::Rgb, 1366, 2)
+            .build()
+            .unwrap();
+        let row = 1366 * 3;
+        let data = (0..2 * row)
+            .map(|i| (i / row) as u8 + 1)
+            .collect::<Vec<_>>();
+
+        let mut buffer = gst::Buffer::from_mut_slice(data);
+        add_packed_video_meta(buffer.get_mut().unwrap(), &info).unwrap();
+        let buffer = repack(buffer, &info).unwrap();
+
+        let map = buffer.map_readable().unwrap();
+        let stride = info.stride()[0] as usize;
+        assert_eq!(map.len(), info.size());
+        assert!(map[..row].iter().all(|&b| b == 1));
+        assert!(map[stride..stride + row].iter().all(|&b| b == 2));
+    }
+
+    #[test]
+    fn max_size_keeps_aspect_ratio() {
+        let settings = Settings {
+            max_width: 1280,
+            max_height: 1280,
+            ..Default::default()
+        };
+
+        assert_eq!(settings.output_size_for(3840, 2160), (1280, 720));
+        assert_eq!(settings.output_size_for(1080, 1920), (720, 1280));
+        assert_eq!(settings.output_size_for(800, 600), (800, 600));
+    }
+
+    #[test]
+    fn one_output_dimension_keeps_aspect_ratio() {
+        let settings = Settings {
+            output_width: 1280,
+            ..Default::default()
+        };
+        assert_eq!(settings.output_size_for(3840, 2160), (1280, 720));
+        assert_eq!(settings.output_size_for(1366, 768), (1280, 720));
+
+        let settings = Settings {
+            output_height: 720,
+            ..Default::default()
+        };
+        assert_eq!(settings.output_size_for(1920, 1200), (1152, 720));
+
+        let settings = Settings {
+            output_width: 640,
+            output_height: 640,
+            ..Default::default()
+        };
+        assert_eq!(settings.output_size_for(1920, 1080), (640, 640));
+    }
+
+    #[test]
+    fn scap_resolution_covers_either_dimension() {
+        use scap::capturer::Resolution;
+
+        let settings = Settings {
+            output_width: 1280,
+            ..Default::default()
+        };
+        assert!(matches!(settings.scap_resolution(), Resolution::_720p));
+
+        let settings = Settings {
+            output_height: 720,
+            ..Default::default()
+        };
+        assert!(matches!(settings.scap_resolution(), Resolution::_720p));
+
+        let settings = Settings {
+            output_width: 640,
+            output_height: 1000,
+            ..Default::default()
+        };
+        assert!(matches!(settings.scap_resolution(), Resolution::_1080p));
+
+        assert!(matches!(
+            Settings::default().scap_resolution(),
+            Resolution::Captured
+        ));
+    }
+
+    #[test]
+    fn even_dimensions_cut_odd_sizes() {
+        let settings = Settings {
+            even_dimensions: true,
+            ..Default::default()
+        };
+
+        assert_eq!(settings.output_size_for(1365, 767), (1364, 766));
+        assert_eq!(settings.output_size_for(1366, 768), (1366, 768));
+        assert_eq!(settings.output_size_for(1, 1), (2, 2));
+    }
+
+    /// Blocks on getting a frame until the sender is dropped
+    struct StalledSource(mpsc::Receiver<()>);
+
+    impl FrameSource for StalledSource {
+        fn next_frame(&self) -> FrameResult {
+            let _ = self.0.recv();
+            Err(mpsc::RecvError)
+        }
+
+        fn stop(&mut self) {}
+    }
+
+    #[test]
+    fn stop_capture_thread_without_frames() {
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let frames = Arc::new(FrameQueue::default());
+        let capture_thread = CaptureThread::spawn(StalledSource(frame_rx), frames.clone());
+
+        let start = Instant::now();
+        capture_thread.stop();
+        assert!(start.elapsed() < CAPTURE_STOP_TIMEOUT * 2);
+
+        // The abandoned thread doesn't queue the frame it was getting
+        drop(frame_tx);
+        assert!(matches!(
+            frames.pop(Some(Duration::from_millis(100))),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn frame_queue_counts_dropped_frames() {
+        let frames = FrameQueue::default();
+        for _ in 0..MAX_QUEUED_FRAMES + 2 {
+            frames.push(Err(mpsc::RecvError));
+        }
+
+        assert_eq!(frames.pop(None).unwrap().unwrap().dropped, 2);
+        assert_eq!(frames.pop(None).unwrap().unwrap().dropped, 0);
+    }
+
+    #[test]
+    fn frame_queue_ends_stream_until_reset() {
+        let frames = FrameQueue::default();
+        frames.push(Err(mpsc::RecvError));
+        frames.set_eos();
+        assert_eq!(frames.pop(None).err(), Some(gst::FlowError::Eos));
+
+        frames.reset();
+        assert!(matches!(frames.pop(Some(Duration::ZERO)), Ok(None)));
+    }
+
+    #[test]
+    fn black_frame_keeps_type_and_size() {
+        let frame = scap::frame::Frame::BGRA(scap::frame::BGRAFrame {
+            display_time: 1,
+            width: 2,
+            height: 1,
+            data: vec![255; 8],
+        });
+
+        let scap::frame::Frame::BGRA(black) = with_display_time(black_frame_like(&frame), 5) else {
+            panic!("Frame type changed");
+        };
+        assert_eq!((black.display_time, black.width, black.height), (5, 2, 1));
+        assert_eq!(black.data, [0, 0, 0, 255, 0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rebase_without_frames_starts_at_zero() {
+        let mut state = State::default();
+
+        state.rebase(7 * SECOND, 30);
+        assert_eq!(state.frame_pts(7 * SECOND, 30), 0);
+    }
+}
@@ -2,13 +2,281 @@
 
 use gst::glib;
 use gst::prelude::*;
+use gst::subclass::prelude::*;
 
 mod imp;
 
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScapSrcOutputFormat")]
+pub enum OutputFormat {
+    #[enum_value(name = "BGR0: Blue, green, red and an unused byte", nick = "bgr0")]
+    Bgr0 = 0,
+    #[enum_value(name = "RGB: Packed red, green, blue", nick = "rgb")]
+    Rgb = 1,
+    #[enum_value(name = "BGRA: Blue, green, red and alpha", nick = "bgra")]
+    Bgra = 2,
+}
+
+impl OutputFormat {
+    fn frame_type(self) -> scap::frame::FrameType {
+        match self {
+            OutputFormat::Bgr0 => scap::frame::FrameType::BGR0,
+            OutputFormat::Rgb => scap::frame::FrameType::RGB,
+            OutputFormat::Bgra => scap::frame::FrameType::BGRAFrame,
+        }
+    }
+
+    fn video_format(self) -> gst_video::VideoFormat {
+        match self {
+            OutputFormat::Bgr0 => gst_video::VideoFormat::Bgrx,
+            OutputFormat::Rgb => gst_video::VideoFormat::Rgb,
+            OutputFormat::Bgra => gst_video::VideoFormat::Bgra,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScapSrcChannelOrder")]
+pub enum ChannelOrder {
+    #[enum_value(name = "RGB: Red, green, blue", nick = "rgb")]
+    Rgb = 0,
+    #[enum_value(name = "BGR: Blue, green, red", nick = "bgr")]
+    Bgr = 1,
+}
+
+impl ChannelOrder {
+    fn of(output_format: OutputFormat) -> Self {
+        match output_format {
+            OutputFormat::Rgb => ChannelOrder::Rgb,
+            OutputFormat::Bgr0 | OutputFormat::Bgra => ChannelOrder::Bgr,
+        }
+    }
+
+    fn output_format(self) -> OutputFormat {
+        match self {
+            ChannelOrder::Rgb => OutputFormat::Rgb,
+            ChannelOrder::Bgr => OutputFormat::Bgr0,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScapSrcScalingMode")]
+pub enum ScalingMode {
+    #[enum_value(name = "Stretch: Scale to the output size", nick = "stretch")]
+    Stretch = 0,
+    #[enum_value(
+        name = "Letterbox: Keep the aspect ratio and add black borders",
+        nick = "letterbox"
+    )]
+    Letterbox = 1,
+    #[enum_value(
+        name = "Crop: Keep the aspect ratio and cut off the edges",
+        nick = "crop"
+    )]
+    Crop = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScapSrcUnsupportedFormatAction")]
+pub enum UnsupportedFormatAction {
+    #[enum_value(name = "Error: Fail with an error", nick = "error")]
+    Error = 0,
+    #[enum_value(name = "Skip: Drop the frame", nick = "skip")]
+    Skip = 1,
+    #[enum_value(
+        name = "Convert: Convert the frame to the output format",
+        nick = "convert"
+    )]
+    Convert = 2,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScapSrcTargetKind")]
+pub enum TargetKind {
+    #[enum_value(
+        name = "Unknown: Not started or picked outside of scapsrc",
+        nick = "unknown"
+    )]
+    Unknown = 0,
+    #[enum_value(name = "Display: A whole display", nick = "display")]
+    Display = 1,
+    #[enum_value(name = "Window: A single window", nick = "window")]
+    Window = 2,
+    #[enum_value(name = "Region: An area of a display", nick = "region")]
+    Region = 3,
+}
+
+/// Type of the `GstContext` `scapsrc` shares its capture parameters with.
+///
+/// It's posted as a `have-context` message whenever caps are negotiated, and
+/// elements in the same pipeline can get it with a context query. Its
+/// structure has the negotiated `width`, `height` and `framerate`, the
+/// `target` property and the `target-kind` of the captured target.
+pub const CAPTURE_CONTEXT_TYPE: &str = "scapsrc.capture";
+
+/// What went wrong in a `scapsrc-error` message
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstScapSrcErrorCode")]
+pub enum ScapErrorCode {
+    #[enum_value(
+        name = "Permission to capture the screen was not granted",
+        nick = "permission"
+    )]
+    Permission = 0,
+    #[enum_value(
+        name = "The captured target stopped delivering frames",
+        nick = "target-lost"
+    )]
+    TargetLost = 1,
+    #[enum_value(name = "Capturing failed but is retried", nick = "transient")]
+    Transient = 2,
+    #[enum_value(name = "Capturing failed and can't continue", nick = "fatal")]
+    Fatal = 3,
+}
+
+/// Error reported by `scapsrc` on the bus, see [`parse_error_message`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScapError {
+    pub code: ScapErrorCode,
+    pub message: String,
+}
+
+impl ScapError {
+    fn to_message(&self, src: &ScapSrc) -> gst::Message {
+        let s = gst::Structure::builder("scapsrc-error")
+            .field("error-code", self.code)
+            .field("message", &self.message)
+            .build();
+        gst::message::Application::builder(s).src(src).build()
+    }
+}
+
+/// Get the error from a `scapsrc-error` application message.
+///
+/// `scapsrc` posts one before every element error it raises for capture
+/// failures, and for failures it recovers from with the `transient` code.
+/// Returns `None` for any other message.
+pub fn parse_error_message(msg: &gst::Message) -> Option<ScapError> {
+    let gst::MessageView::Application(app) = msg.view() else {
+        return None;
+    };
+    let s = app.structure()?;
+    if s.name() != "scapsrc-error" {
+        return None;
+    }
+
+    Some(ScapError {
+        code: s.get("error-code").ok()?,
+        message: s.get("message").ok()?,
+    })
+}
+
 glib::wrapper! {
     pub struct ScapSrc(ObjectSubclass<imp::ScapSrc>) @extends gst_base::PushSrc, gst_base::BaseSrc, gst::Element, gst::Object;
 }
 
+impl ScapSrc {
+    /// Get the next frame the element produces while playing.
+    ///
+    /// Waits up to `timeout` for the frame, so every call returns a new one.
+    /// Returns `None` if the element isn't playing or no frame was produced in
+    /// time. Use `for_each_frame()` to capture without a pipeline.
+    pub fn try_pull_frame(
+        &self,
+        timeout: impl Into<Option<gst::ClockTime>>,
+    ) -> Option<gst::Sample> {
+        self.imp().try_pull_frame(timeout.into())
+    }
+
+    /// Capture frames and pass each one to `f` without setting up a pipeline.
+    ///
+    /// The element is put in a pipeline with an `appsink`, so it must not be
+    /// in another bin already. Frames are delivered in the negotiated format
+    /// on a streaming thread until the returned handle is stopped or dropped,
+    /// which takes the element out of that pipeline again.
+    pub fn for_each_frame<F>(&self, mut f: F) -> Result<FrameCapture, glib::BoolError>
+    where
+        F: FnMut(gst::Sample) + Send + 'static,
+    {
+        let appsink = gst_app::AppSink::builder()
+            .sync(false)
+            .callbacks(
+                gst_app::AppSinkCallbacks::builder()
+                    .new_sample(move |appsink| {
+                        let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        f(sample);
+                        Ok(gst::FlowSuccess::Ok)
+                    })
+                    .build(),
+            )
+            .build();
+
+        let pipeline = gst::Pipeline::default();
+        pipeline.add_many([self.upcast_ref(), appsink.upcast_ref::<gst::Element>()])?;
+
+        // Takes the element out of the pipeline again if starting fails
+        let capture = FrameCapture {
+            pipeline,
+            src: self.clone(),
+        };
+        self.link(&appsink)?;
+        capture
+            .pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|_| glib::bool_error!("Failed to start capturing"))?;
+
+        Ok(capture)
+    }
+}
+
+/// Handle to a capture started with [`ScapSrc::for_each_frame`]
+#[derive(Debug)]
+pub struct FrameCapture {
+    pipeline: gst::Pipeline,
+    src: ScapSrc,
+}
+
+impl FrameCapture {
+    /// Block until capturing ends, either at EOS, e.g. once the `duration` is
+    /// reached, or because of an error.
+    pub fn wait(&self) -> Result<(), glib::Error> {
+        let bus = self.pipeline.bus().expect("pipelines have a bus");
+        for msg in bus.iter_timed(gst::ClockTime::NONE) {
+            match msg.view() {
+                gst::MessageView::Eos(_) => break,
+                gst::MessageView::Error(err) => return Err(err.error()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stop capturing. The callback isn't called anymore once this returns,
+    /// and the element can be added to another bin.
+    ///
+    /// Called when the handle is dropped.
+    pub fn stop(&self) {
+        let _ = self.pipeline.set_state(gst::State::Null);
+        if self.src.parent().as_ref() == Some(self.pipeline.upcast_ref::<gst::Object>()) {
+            let _ = self.pipeline.remove(&self.src);
+        }
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
 pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     gst::Element::register(
         Some(plugin),
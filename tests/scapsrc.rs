@@ -0,0 +1,453 @@
+// Copyright (C) 2024-2025 Marcus L. Hanestad <marlhan@proton.me>
+
+use gst::prelude::*;
+use std::sync::Once;
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        scapgst::register().unwrap();
+    });
+}
+
+#[test]
+fn property_defaults() {
+    init();
+
+    let src = gst::ElementFactory::make("scapsrc").build().unwrap();
+
+    assert_eq!(src.property::<u32>("fps"), 25);
+    assert!(src.property::<bool>("show-cursor"));
+    assert!(!src.property::<bool>("paused"));
+    assert_eq!(src.property::<u64>("duration"), u64::MAX);
+    assert_eq!(src.property::<Option<String>>("target-resolution"), None);
+    assert_eq!(src.property::<Option<gst::Caps>>("current-caps"), None);
+}
+
+#[test]
+fn property_round_trip() {
+    init();
+
+    let src = gst::ElementFactory::make("scapsrc")
+        .property("fps", 30u32)
+        .property("max-fps", 10u32)
+        .property("output-width", 640u32)
+        .property("output-height", 480u32)
+        .property_from_str("scaling-mode", "letterbox")
+        .build()
+        .unwrap();
+
+    assert_eq!(src.property::<u32>("fps"), 30);
+    assert_eq!(src.property::<u32>("max-fps"), 10);
+    assert_eq!(src.property::<u32>("output-width"), 640);
+    assert_eq!(src.property::<u32>("output-height"), 480);
+}
+
+fn src_template_caps() -> gst::Caps {
+    let factory = gst::ElementFactory::find("scapsrc").unwrap();
+    let template = factory
+        .static_pad_templates()
+        .into_iter()
+        .find(|t| t.name_template() == "src")
+        .unwrap();
+    template.caps()
+}
+
+#[test]
+fn pad_template_caps() {
+    init();
+
+    let caps = src_template_caps();
+
+    for format in ["RGB", "RGBx", "xBGR", "BGRx", "BGRA"] {
+        let expected = gst::Caps::builder("video/x-raw")
+            .field("format", format)
+            .field("width", 1920)
+            .field("height", 1080)
+            .field("framerate", gst::Fraction::new(30, 1))
+            .build();
+        assert!(expected.can_intersect(&caps), "{format} not in {caps}");
+    }
+}
+
+#[test]
+fn pad_template_formats_are_unique() {
+    init();
+
+    let caps = src_template_caps();
+    let formats = caps
+        .structure(0)
+        .unwrap()
+        .get::<gst::List>("format")
+        .unwrap();
+    let formats = formats
+        .iter()
+        .map(|format| format.get::<&str>().unwrap())
+        .collect::<Vec<_>>();
+
+    let mut unique = formats.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    assert_eq!(
+        formats.len(),
+        unique.len(),
+        "duplicate formats in {formats:?}"
+    );
+}
+
+#[test]
+fn parse_error_message() {
+    init();
+
+    let s = gst::Structure::builder("scapsrc-error")
+        .field("error-code", scapgst::ScapErrorCode::TargetLost)
+        .field("message", "gone")
+        .build();
+    let msg = gst::message::Application::new(s);
+
+    assert_eq!(
+        scapgst::parse_error_message(&msg),
+        Some(scapgst::ScapError {
+            code: scapgst::ScapErrorCode::TargetLost,
+            message: "gone".to_string(),
+        })
+    );
+
+    let other = gst::message::Application::new(gst::Structure::new_empty("other"));
+    assert_eq!(scapgst::parse_error_message(&other), None);
+}
+
+// Only checks anything where there's nothing to capture, e.g. in headless CI
+#[test]
+fn start_fails_without_targets() {
+    init();
+
+    let headless = if cfg!(target_os = "linux") {
+        std::env::var_os("WAYLAND_DISPLAY").is_none() && std::env::var_os("DISPLAY").is_none()
+    } else {
+        let src = gst::ElementFactory::make("scapsrc").build().unwrap();
+        src.emit_by_name::<gst::glib::ValueArray>("get-targets", &[])
+            .is_empty()
+    };
+    if !headless {
+        return;
+    }
+
+    let pipeline = gst::parse::launch("scapsrc ! fakesink").unwrap();
+    let _ = pipeline.set_state(gst::State::Playing);
+
+    let msg = pipeline
+        .bus()
+        .unwrap()
+        .timed_pop_filtered(gst::ClockTime::from_seconds(30), &[gst::MessageType::Error])
+        .expect("Starting without targets didn't fail");
+    let gst::MessageView::Error(err) = msg.view() else {
+        unreachable!();
+    };
+    // On Linux, where scap doesn't list targets, setting up capturing fails
+    if !cfg!(target_os = "linux") {
+        assert!(err.error().matches(gst::ResourceError::NotFound));
+    }
+
+    pipeline.set_state(gst::State::Null).unwrap();
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn capture_timestamps_and_eos() {
+    init();
+
+    let mut h = gst_check::Harness::new("scapsrc");
+    {
+        let src = h.element().unwrap();
+        src.set_property("fps", 10u32);
+        src.set_property("duration", gst::ClockTime::SECOND.nseconds());
+    }
+    h.play();
+
+    let mut last_pts = None;
+    let mut buffers = 0;
+    while let Some(buffer) = h.pull_until_eos().unwrap() {
+        let pts = buffer.pts().unwrap();
+        assert!(last_pts.map_or(true, |last| pts > last));
+        assert!(pts <= gst::ClockTime::SECOND);
+        assert!(buffer.duration().is_some());
+        last_pts = Some(pts);
+        buffers += 1;
+    }
+    assert!(buffers > 0);
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+    assert!(info.width() > 0 && info.height() > 0);
+    assert_eq!(info.fps(), gst::Fraction::new(10, 1));
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn internal_preroll_frame_is_delivered_first() {
+    init();
+
+    let mut h = gst_check::Harness::new("scapsrc");
+    h.element()
+        .unwrap()
+        .set_property("perform-internal-preroll", true);
+    h.play();
+
+    // The prerolled frame is the first one captured and starts the timeline
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.offset(), 0);
+    assert_eq!(buffer.pts(), Some(gst::ClockTime::ZERO));
+
+    let buffer = h.pull().unwrap();
+    assert_eq!(buffer.offset(), 1);
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn reconfigure_changes_output_format() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse(
+        "scapsrc auto-convert=true ! capsfilter name=filter caps=video/x-raw,format=BGRx",
+    );
+    h.play();
+
+    let format = |h: &gst_check::Harness| {
+        let caps = h.sinkpad().unwrap().current_caps().unwrap();
+        gst_video::VideoInfo::from_caps(&caps).unwrap().format()
+    };
+
+    h.pull().unwrap();
+    assert_eq!(format(&h), gst_video::VideoFormat::Bgrx);
+
+    let filter = h
+        .element()
+        .unwrap()
+        .downcast::<gst::Bin>()
+        .unwrap()
+        .by_name("filter")
+        .unwrap();
+    filter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("format", "I420")
+            .build(),
+    );
+
+    // Frames already in flight may still have the old format
+    for _ in 0..5 {
+        h.pull().unwrap();
+    }
+    assert_eq!(format(&h), gst_video::VideoFormat::I420);
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn stop_while_waiting_for_frame() {
+    init();
+
+    let mut h = gst_check::Harness::new("scapsrc");
+    let src = h.element().unwrap();
+    src.set_property("fps", 1u32);
+    h.play();
+    h.pull().unwrap();
+
+    // At 1 fps the next `create()` is still waiting for a frame. Stopping
+    // only waits for scap to deliver the frame it's getting
+    let start = std::time::Instant::now();
+    src.set_state(gst::State::Null).unwrap();
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn stop_during_internal_preroll() {
+    init();
+
+    let pipeline = gst::parse::launch("scapsrc perform-internal-preroll=true ! fakesink").unwrap();
+
+    // Stop at different points of starting, from before the capturer is built
+    // to after the preroll frame was pulled
+    for i in 0..20 {
+        pipeline.set_state(gst::State::Playing).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(i * 10));
+        pipeline.set_state(gst::State::Null).unwrap();
+    }
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn num_buffers_ends_before_size_change() {
+    init();
+
+    let mut h = gst_check::Harness::new_parse(
+        "scapsrc num-buffers=1 auto-convert=true ! capsfilter name=filter",
+    );
+    h.play();
+    h.pull().unwrap();
+
+    // Ask for another size for the second frame, which is never captured
+    let filter = h
+        .element()
+        .unwrap()
+        .downcast::<gst::Bin>()
+        .unwrap()
+        .by_name("filter")
+        .unwrap();
+    filter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("width", 320)
+            .field("height", 240)
+            .build(),
+    );
+
+    assert!(h.pull_until_eos().unwrap().is_none());
+    assert_eq!(h.buffers_received(), 1);
+    h.element().unwrap().set_state(gst::State::Null).unwrap();
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn video_meta_when_allocation_query_lists_it() {
+    init();
+
+    let mut h = gst_check::Harness::new("scapsrc");
+    h.add_propose_allocation_meta(gst_video::VideoMeta::meta_api(), None);
+
+    // Check the allocation query once it was answered downstream
+    let listed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    h.element().unwrap().static_pad("src").unwrap().add_probe(
+        gst::PadProbeType::QUERY_DOWNSTREAM | gst::PadProbeType::PULL,
+        {
+            let listed = listed.clone();
+            move |_, info| {
+                if let Some(gst::QueryView::Allocation(query)) =
+                    info.query().map(|query| query.view())
+                {
+                    listed.store(
+                        query
+                            .find_allocation_meta::<gst_video::VideoMeta>()
+                            .is_some(),
+                        std::sync::atomic::Ordering::SeqCst,
+                    );
+                }
+                gst::PadProbeReturn::Ok
+            }
+        },
+    );
+    h.play();
+
+    let buffer = h.pull().unwrap();
+    assert!(listed.load(std::sync::atomic::Ordering::SeqCst));
+
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    let info = gst_video::VideoInfo::from_caps(&caps).unwrap();
+    let meta = buffer.meta::<gst_video::VideoMeta>().unwrap();
+    assert_eq!(meta.format(), info.format());
+    assert_eq!((meta.width(), meta.height()), (info.width(), info.height()));
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn internal_preroll_then_wait_before_playing() {
+    init();
+
+    let mut h = gst_check::Harness::new("scapsrc");
+    let src = h.element().unwrap();
+    src.set_property("perform-internal-preroll", true);
+
+    // Starting, and with it the internal preroll, completes asynchronously
+    let _ = src.set_state(gst::State::Paused);
+    let (res, ..) = src.state(gst::ClockTime::from_seconds(30));
+    res.unwrap();
+    std::thread::sleep(std::time::Duration::from_secs(2));
+    h.play();
+
+    // The time spent in PAUSED doesn't show up in the timestamps
+    let first = h.pull().unwrap().pts().unwrap();
+    let second = h.pull().unwrap().pts().unwrap();
+    assert!(first < gst::ClockTime::from_mseconds(100));
+    assert!(second < gst::ClockTime::from_mseconds(500));
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn buffers_grow_with_resolution_increase() {
+    init();
+
+    let small = gst_video::VideoInfo::builder(gst_video::VideoFormat::Bgrx, 320, 240)
+        .build()
+        .unwrap();
+    let large = gst_video::VideoInfo::builder(gst_video::VideoFormat::Bgrx, 1280, 720)
+        .build()
+        .unwrap();
+
+    let mut h = gst_check::Harness::new_parse(
+        "scapsrc auto-convert=true ! capsfilter name=filter caps=video/x-raw,format=BGRx,width=320,height=240",
+    );
+    h.play();
+    assert_eq!(h.pull().unwrap().size(), small.size());
+
+    let filter = h
+        .element()
+        .unwrap()
+        .downcast::<gst::Bin>()
+        .unwrap()
+        .by_name("filter")
+        .unwrap();
+    filter.set_property(
+        "caps",
+        gst::Caps::builder("video/x-raw")
+            .field("format", "BGRx")
+            .field("width", 1280)
+            .field("height", 720)
+            .build(),
+    );
+
+    // Frames already in flight may still have the old size, but none are
+    // truncated to a buffer sized for it
+    let mut size = 0;
+    for _ in 0..5 {
+        size = h.pull().unwrap().size();
+        assert!(size == small.size() || size == large.size());
+    }
+    assert_eq!(size, large.size());
+}
+
+// Needs a display and permission to capture it
+#[cfg(feature = "capture-tests")]
+#[test]
+fn for_each_frame_releases_element() {
+    init();
+
+    let src = gst::ElementFactory::make("scapsrc")
+        .build()
+        .unwrap()
+        .downcast::<scapgst::ScapSrc>()
+        .unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let capture = src
+        .for_each_frame(move |sample| {
+            let _ = sender.send(sample);
+        })
+        .unwrap();
+    receiver
+        .recv_timeout(std::time::Duration::from_secs(30))
+        .unwrap();
+
+    drop(capture);
+    assert!(src.parent().is_none());
+    assert_eq!(src.current_state(), gst::State::Null);
+}